@@ -0,0 +1,952 @@
+//! Iterative agent loop.
+//!
+//! Sends the conversation to a provider, runs any requested tool calls,
+//! feeds the results back and stops on a final answer or when the
+//! iteration limit, token budget, cost cap or run deadline is reached.
+
+use crate::cancel::CancelToken;
+use crate::config::CompactStrategy;
+use crate::context;
+use crate::cost::{self, Price};
+use crate::events::{Event, Events};
+use crate::message::{ContentBlock, Message, Role, ToolCall, ToolDefinition, ToolOutput, Usage};
+use crate::output;
+use crate::provider::{self, Provider};
+use crate::session::{self, ReplyStats, Session, SessionStore};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+const SYSTEM_PROMPT: &str = "You are an autonomous agent. Work on the user's task step by step, \
+using the available tools when needed. When the task is complete, reply with a final answer \
+and no tool calls.";
+
+/// Executes tool calls on behalf of the loop.
+pub trait ToolDispatcher {
+    /// Tools offered to the model.
+    fn definitions(&self) -> Vec<ToolDefinition>;
+
+    /// Run a single tool call.
+    fn dispatch(&mut self, call: &ToolCall) -> ToolOutput;
+}
+
+/// How a run ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunOutcome {
+    /// Not run yet, e.g. a new fork.
+    Pending,
+    /// The model replied without requesting tools.
+    Completed,
+    /// `max_iterations` was reached before a final answer.
+    MaxIterations,
+    /// The run used up its token budget before a final answer.
+    BudgetExhausted,
+    /// The next model call could have taken the run over its cost cap.
+    CostCapReached,
+    /// The provider returned an error.
+    Failed { error: String },
+    /// The run was cancelled before a final answer.
+    Cancelled,
+    /// The run deadline passed before a final answer.
+    TimedOut,
+}
+
+impl RunOutcome {
+    /// Short status name, as serialized.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Completed => "completed",
+            Self::MaxIterations => "max_iterations",
+            Self::BudgetExhausted => "budget_exhausted",
+            Self::CostCapReached => "cost_cap_reached",
+            Self::Failed { .. } => "failed",
+            Self::Cancelled => "cancelled",
+            Self::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// Record of one executed tool call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    pub iteration: u64,
+    pub name: String,
+    pub input: serde_json::Value,
+    pub is_error: bool,
+}
+
+/// Token budget of the latest run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenBudget {
+    pub limit: u64,
+    /// Input plus output tokens used by the run.
+    pub used: u64,
+}
+
+impl TokenBudget {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+/// Spending cap of the latest run, in USD.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CostCap {
+    pub limit_usd: f64,
+    pub spent_usd: f64,
+}
+
+/// Structured result of an agent run.
+///
+/// Iterations, tool calls and usage accumulate across resumed runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub task: String,
+    pub outcome: RunOutcome,
+    pub iterations: u64,
+    pub final_answer: Option<String>,
+    pub tool_calls: Vec<ToolCallRecord>,
+    pub usage: Usage,
+    /// Wall time spent in runs, in milliseconds.
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub budget: Option<TokenBudget>,
+    /// Cost in USD of the replies whose model had a price.
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
+    #[serde(default)]
+    pub cost_cap: Option<CostCap>,
+}
+
+impl RunSummary {
+    /// Human-readable summary for CLI output.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&format!("Agent Task: {}\n", self.task));
+        let outcome = match &self.outcome {
+            RunOutcome::Pending => "not run yet".to_string(),
+            RunOutcome::Completed => "completed".to_string(),
+            RunOutcome::MaxIterations => "stopped (max iterations reached)".to_string(),
+            RunOutcome::BudgetExhausted => "stopped (token budget exhausted)".to_string(),
+            RunOutcome::CostCapReached => "stopped (cost cap reached)".to_string(),
+            RunOutcome::Failed { error } => format!("failed: {}", error),
+            RunOutcome::Cancelled => "cancelled".to_string(),
+            RunOutcome::TimedOut => "stopped (run deadline reached)".to_string(),
+        };
+        output.push_str(&format!("Outcome: {}\n", outcome));
+        output.push_str(&format!("Iterations: {}\n", self.iterations));
+        output.push_str(&format!(
+            "Tokens: {} in / {} out\n",
+            self.usage.input_tokens, self.usage.output_tokens
+        ));
+        if let Some(budget) = &self.budget {
+            output.push_str(&format!(
+                "Token budget: {} of {} used ({} remaining)\n",
+                budget.used,
+                budget.limit,
+                budget.remaining()
+            ));
+        }
+        if let Some(cost) = self.cost_usd {
+            output.push_str(&format!("Cost: {}\n", cost::format_usd(cost)));
+        }
+        if let Some(cap) = &self.cost_cap {
+            output.push_str(&format!(
+                "Cost cap: {} of {} spent ({} remaining)\n",
+                cost::format_usd(cap.spent_usd),
+                cost::format_usd(cap.limit_usd),
+                cost::format_usd((cap.limit_usd - cap.spent_usd).max(0.0))
+            ));
+        }
+        output.push_str(&format!(
+            "Duration: {}\n",
+            format_duration(self.duration_ms)
+        ));
+
+        if !self.tool_calls.is_empty() {
+            output.push_str(&format!("\nTool calls ({}):\n", self.tool_calls.len()));
+            for call in &self.tool_calls {
+                let status = if call.is_error { " (error)" } else { "" };
+                output.push_str(&format!(
+                    "  [{}] {} {}{}\n",
+                    call.iteration, call.name, call.input, status
+                ));
+            }
+        }
+
+        if let Some(answer) = &self.final_answer {
+            output.push_str(&format!("\nFinal answer:\n{}\n", answer));
+        }
+
+        output.trim_end().to_string()
+    }
+}
+
+/// `1.5s`, `2m 05s` or `1h 02m` for display.
+pub fn format_duration(ms: u64) -> String {
+    let seconds = ms / 1000;
+    if seconds < 60 {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    } else if seconds < 3600 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60)
+    }
+}
+
+/// Agent loop over a provider and a tool dispatcher.
+pub struct AgentLoop<'a> {
+    provider: &'a mut dyn Provider,
+    tools: &'a mut dyn ToolDispatcher,
+    max_iterations: u64,
+    token_budget: Option<u64>,
+    price: Option<Price>,
+    cost_cap: Option<f64>,
+    /// Output tokens a call may use, for projecting its cost.
+    max_output_tokens: u64,
+    deadline: Option<Instant>,
+    /// Strategy and token threshold of context compaction.
+    compaction: Option<(CompactStrategy, u64)>,
+    cancel: CancelToken,
+    store: Option<SessionStore>,
+    events: Events,
+}
+
+impl<'a> AgentLoop<'a> {
+    pub fn new(
+        provider: &'a mut dyn Provider,
+        tools: &'a mut dyn ToolDispatcher,
+        max_iterations: u64,
+    ) -> Self {
+        Self {
+            provider,
+            tools,
+            max_iterations,
+            token_budget: None,
+            price: None,
+            cost_cap: None,
+            max_output_tokens: 0,
+            deadline: None,
+            compaction: None,
+            cancel: CancelToken::new(),
+            store: None,
+            events: Events::default(),
+        }
+    }
+
+    /// Stop the run once it has used `limit` input plus output tokens.
+    ///
+    /// The budget is checked before each model call, so the call that crosses
+    /// it completes and its tool calls still run.
+    pub fn with_token_budget(mut self, limit: u64) -> Self {
+        self.token_budget = Some(limit);
+        self
+    }
+
+    /// Record the cost of each reply at `price`.
+    pub fn with_price(mut self, price: Price) -> Self {
+        self.price = Some(price);
+        self
+    }
+
+    /// Stop the run before a model call that could take its cost over
+    /// `limit` USD.
+    ///
+    /// A call is projected at its estimated input plus `max_output_tokens`
+    /// of output. Without a price the cap is not enforced.
+    pub fn with_cost_cap(mut self, limit: f64, max_output_tokens: u64) -> Self {
+        self.cost_cap = Some(limit);
+        self.max_output_tokens = max_output_tokens;
+        self
+    }
+
+    /// Stop the run at `deadline`.
+    ///
+    /// The cancel token fires at the deadline, which abandons a model call
+    /// in flight and kills running tool subprocesses.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Compact older turns with `strategy` once the conversation is
+    /// estimated at more than `threshold` tokens.
+    pub fn with_compaction(mut self, strategy: CompactStrategy, threshold: u64) -> Self {
+        self.compaction = (strategy != CompactStrategy::Off).then_some((strategy, threshold));
+        self
+    }
+
+    /// Stop the run once `cancel` fires.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Save the session to `store` after every iteration.
+    pub fn with_store(mut self, store: SessionStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Report progress to `events`, streaming model replies as they are
+    /// generated.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Continue `session` until a final answer, `max_iterations` more model
+    /// calls, the token budget or cost cap is used up, or the deadline passes.
+    pub fn run(&mut self, session: &mut Session) {
+        let definitions = self.tools.definitions();
+        session.summary.outcome = RunOutcome::MaxIterations;
+        session.summary.budget = self
+            .token_budget
+            .map(|limit| TokenBudget { limit, used: 0 });
+        session.summary.cost_cap = self.cost_cap.map(|limit_usd| CostCap {
+            limit_usd,
+            spent_usd: 0.0,
+        });
+        let run_started = Instant::now();
+        let _watchdog = self.deadline.map(|at| self.cancel.cancel_at(at));
+        let mut iterations = 0;
+        self.events.emit(Event::RunStarted {
+            task: &session.summary.task,
+            provider: &session.provider,
+            model: &session.model,
+        });
+
+        while iterations < self.max_iterations {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            if let Some((strategy, threshold)) = self.compaction {
+                if let Err(outcome) = self.compact(session, &definitions, strategy, threshold) {
+                    if !self.cancel.is_cancelled() {
+                        session.summary.outcome = outcome;
+                    }
+                    break;
+                }
+            }
+            let context = context::view(session);
+            if let Some(outcome) = limit_reached(
+                &session.summary,
+                self.price,
+                self.max_output_tokens,
+                SYSTEM_PROMPT,
+                &context,
+                &definitions,
+            ) {
+                session.summary.outcome = outcome;
+                break;
+            }
+            iterations += 1;
+            let started = Instant::now();
+            let summary = &mut session.summary;
+            summary.iterations += 1;
+
+            let reply = if self.events.is_enabled() {
+                let events = &self.events;
+                self.provider.complete_streaming(
+                    SYSTEM_PROMPT,
+                    &context,
+                    &definitions,
+                    &mut |text| events.emit(Event::TextDelta { text }),
+                )
+            } else {
+                self.provider
+                    .complete(SYSTEM_PROMPT, &context, &definitions)
+            };
+            let response = match reply {
+                Ok(response) => response,
+                // A call abandoned on cancel is not a provider failure.
+                Err(_) if self.cancel.is_cancelled() => break,
+                Err(error) => {
+                    summary.outcome = RunOutcome::Failed { error };
+                    break;
+                }
+            };
+            let usage = response.usage.unwrap_or_else(|| {
+                provider::estimate_usage(SYSTEM_PROMPT, &context, &definitions, &response.content)
+            });
+            let cost_usd = self.charge(summary, usage);
+            self.events.emit(Event::Usage {
+                iteration: summary.iterations,
+                usage,
+                total: summary.usage,
+                cost_usd: summary.cost_usd,
+            });
+
+            let message = Message::assistant(response.content);
+            let calls = message.tool_calls();
+            let text = message.text();
+            session.messages.push(message);
+            session.replies.push(ReplyStats {
+                usage,
+                duration_ms: elapsed_ms(started),
+                model: session.model.clone(),
+                at: session::now(),
+                cost_usd,
+            });
+
+            if calls.is_empty() {
+                summary.outcome = RunOutcome::Completed;
+                summary.final_answer = Some(text);
+                break;
+            }
+
+            let mut results = Vec::with_capacity(calls.len());
+            for call in &calls {
+                // Calls after an abort are answered without running them.
+                let output = if self.cancel.is_cancelled() {
+                    ToolOutput::error("Run cancelled before this tool call")
+                } else {
+                    self.events.emit(Event::ToolCallStarted {
+                        id: &call.id,
+                        name: &call.name,
+                        input: &call.input,
+                    });
+                    let started = Instant::now();
+                    let output = self.tools.dispatch(call);
+                    self.events.emit(Event::ToolCallFinished {
+                        id: &call.id,
+                        name: &call.name,
+                        is_error: output.is_error,
+                        duration_ms: elapsed_ms(started),
+                        output: &output.content,
+                    });
+                    output
+                };
+                summary.tool_calls.push(ToolCallRecord {
+                    iteration: summary.iterations,
+                    name: call.name.clone(),
+                    input: call.input.clone(),
+                    is_error: output.is_error,
+                });
+                results.push(ContentBlock::ToolResult {
+                    tool_use_id: call.id.clone(),
+                    content: output.content,
+                    is_error: output.is_error,
+                });
+            }
+            session.messages.push(Message {
+                role: Role::User,
+                content: results,
+            });
+            // The reply's time includes running the tools it asked for.
+            if let Some(reply) = session.replies.last_mut() {
+                reply.duration_ms = elapsed_ms(started);
+            }
+            self.checkpoint(session);
+        }
+
+        if self.cancel.is_cancelled() && session.summary.outcome == RunOutcome::MaxIterations {
+            let timed_out = self.deadline.is_some_and(|at| Instant::now() >= at);
+            session.summary.outcome = if timed_out {
+                RunOutcome::TimedOut
+            } else {
+                RunOutcome::Cancelled
+            };
+        }
+        session.summary.duration_ms += elapsed_ms(run_started);
+        session.updated_at = session::now();
+        self.events.emit(Event::RunFinished {
+            summary: output::run_summary(&session.summary),
+        });
+    }
+
+    /// Add a model call's usage and cost to the run totals, returning its cost.
+    fn charge(&self, summary: &mut RunSummary, usage: Usage) -> Option<f64> {
+        summary.usage.add(usage);
+        if let Some(budget) = &mut summary.budget {
+            budget.used += usage.total();
+        }
+        let cost_usd = self.price.map(|price| price.cost(&usage));
+        if let Some(cost) = cost_usd {
+            *summary.cost_usd.get_or_insert(0.0) += cost;
+            if let Some(cap) = &mut summary.cost_cap {
+                cap.spent_usd += cost;
+            }
+        }
+        cost_usd
+    }
+
+    /// Compact the session's context if it is over `threshold` tokens.
+    ///
+    /// The summarizing call is checked against the token budget and cost cap
+    /// like any other model call; the error is the outcome to stop with.
+    fn compact(
+        &mut self,
+        session: &mut Session,
+        definitions: &[ToolDefinition],
+        strategy: CompactStrategy,
+        threshold: u64,
+    ) -> Result<(), RunOutcome> {
+        let tokens = context::tokens(SYSTEM_PROMPT, &context::view(session), definitions);
+        if tokens <= threshold {
+            return Ok(());
+        }
+        let (price, max_output_tokens) = (self.price, self.max_output_tokens);
+        let mut stop = None;
+        let compaction = context::compact(
+            self.provider,
+            session,
+            strategy,
+            tokens,
+            &mut |system, messages| {
+                stop = limit_reached(
+                    &session.summary,
+                    price,
+                    max_output_tokens,
+                    system,
+                    messages,
+                    &[],
+                );
+                stop.is_none()
+            },
+        )
+        .map_err(|error| RunOutcome::Failed {
+            error: format!("Context compaction failed: {}", error),
+        })?;
+        if let Some(outcome) = stop {
+            return Err(outcome);
+        }
+        let Some(mut compaction) = compaction else {
+            return Ok(());
+        };
+        if strategy == CompactStrategy::Summarize {
+            compaction.cost_usd = self.charge(&mut session.summary, compaction.usage);
+        }
+        session.compactions.push(compaction);
+        let tokens_after = context::tokens(SYSTEM_PROMPT, &context::view(session), definitions);
+        if let Some(compaction) = session.compactions.last_mut() {
+            compaction.tokens_after = tokens_after;
+        }
+        self.checkpoint(session);
+        Ok(())
+    }
+
+    fn checkpoint(&self, session: &mut Session) {
+        session.updated_at = session::now();
+        if let Some(store) = &self.store {
+            // Best effort; the caller saves the final state and reports errors.
+            let _ = store.save(session);
+        }
+    }
+}
+
+/// Why the run must stop before a model call with this input, if it must.
+///
+/// The budget stops the run once it is used up; the cost cap stops it when
+/// the call, at `max_output_tokens` of output, could go over the cap.
+fn limit_reached(
+    summary: &RunSummary,
+    price: Option<Price>,
+    max_output_tokens: u64,
+    system: &str,
+    messages: &[Message],
+    tools: &[ToolDefinition],
+) -> Option<RunOutcome> {
+    if let Some(budget) = &summary.budget {
+        if budget.used >= budget.limit {
+            return Some(RunOutcome::BudgetExhausted);
+        }
+    }
+    if let (Some(cap), Some(price)) = (&summary.cost_cap, price) {
+        let next = Usage {
+            output_tokens: max_output_tokens,
+            ..provider::estimate_usage(system, messages, tools, &[])
+        };
+        if cap.spent_usd + price.cost(&next) > cap.limit_usd {
+            return Some(RunOutcome::CostCapReached);
+        }
+    }
+    None
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use crate::events::tests::Collect;
+    use crate::provider::ProviderResponse;
+    use serde_json::json;
+    use std::collections::VecDeque;
+
+    /// Replays `replies` in order; summarizing calls get a fixed summary.
+    struct Scripted {
+        replies: VecDeque<ProviderResponse>,
+        /// Messages sent with each agent call.
+        sent: Vec<usize>,
+        summaries: usize,
+    }
+
+    impl Scripted {
+        fn new(replies: Vec<ProviderResponse>) -> Self {
+            Self {
+                replies: replies.into(),
+                sent: Vec::new(),
+                summaries: 0,
+            }
+        }
+    }
+
+    impl Provider for Scripted {
+        fn complete(
+            &mut self,
+            system: &str,
+            messages: &[Message],
+            _tools: &[ToolDefinition],
+        ) -> Result<ProviderResponse, String> {
+            if system != SYSTEM_PROMPT {
+                self.summaries += 1;
+                return Ok(answer("Read the first files; nothing failed.", 50));
+            }
+            self.sent.push(messages.len());
+            self.replies
+                .pop_front()
+                .ok_or_else(|| "script exhausted".to_string())
+        }
+    }
+
+    /// Answers every call with `output`.
+    struct Tools {
+        output: String,
+    }
+
+    impl ToolDispatcher for Tools {
+        fn definitions(&self) -> Vec<ToolDefinition> {
+            Vec::new()
+        }
+
+        fn dispatch(&mut self, _call: &ToolCall) -> ToolOutput {
+            ToolOutput::ok(self.output.clone())
+        }
+    }
+
+    fn tools(output_len: usize) -> Tools {
+        Tools {
+            output: "x".repeat(output_len),
+        }
+    }
+
+    fn answer(text: &str, input_tokens: u64) -> ProviderResponse {
+        ProviderResponse {
+            content: vec![ContentBlock::Text {
+                text: text.to_string(),
+            }],
+            usage: Some(Usage {
+                input_tokens,
+                output_tokens: 10,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// A reply asking for one `read` call.
+    fn read(input_tokens: u64) -> ProviderResponse {
+        ProviderResponse {
+            content: vec![ContentBlock::ToolUse {
+                id: format!("call_{}", input_tokens),
+                name: "read".to_string(),
+                input: json!({ "path": "notes.txt" }),
+            }],
+            usage: Some(Usage {
+                input_tokens,
+                output_tokens: 10,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn final_answer_completes_the_run() {
+        let mut provider = Scripted::new(vec![read(5), answer("Done.", 7)]);
+        let mut tools = tools(3);
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut tools, 10).run(&mut session);
+
+        let summary = &session.summary;
+        assert_eq!(summary.outcome, RunOutcome::Completed);
+        assert_eq!(summary.final_answer.as_deref(), Some("Done."));
+        assert_eq!(summary.iterations, 2);
+        assert_eq!(summary.tool_calls.len(), 1);
+        assert_eq!(summary.tool_calls[0].name, "read");
+        assert_eq!(summary.usage.input_tokens, 12);
+        assert_eq!(session.messages.len(), 4);
+        assert_eq!(session.replies.len(), 2);
+        assert_eq!(
+            session.messages[2].content,
+            vec![ContentBlock::ToolResult {
+                tool_use_id: "call_5".to_string(),
+                content: "xxx".to_string(),
+                is_error: false,
+            }]
+        );
+        assert_eq!(provider.sent, vec![1, 3]);
+    }
+
+    #[test]
+    fn iteration_limit_stops_the_run() {
+        let mut provider = Scripted::new((1..=5).map(read).collect());
+        let mut tools = tools(3);
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut tools, 3).run(&mut session);
+
+        assert_eq!(session.summary.outcome, RunOutcome::MaxIterations);
+        assert_eq!(session.summary.iterations, 3);
+        assert_eq!(session.summary.final_answer, None);
+        assert_eq!(provider.sent.len(), 3);
+    }
+
+    #[test]
+    fn provider_error_fails_the_run() {
+        let mut provider = Scripted::new(vec![read(1)]);
+        let mut tools = tools(3);
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut tools, 10).run(&mut session);
+
+        assert_eq!(
+            session.summary.outcome,
+            RunOutcome::Failed {
+                error: "script exhausted".to_string()
+            }
+        );
+        assert_eq!(session.summary.iterations, 2);
+    }
+
+    #[test]
+    fn cancelled_run_stops_before_the_next_call() {
+        struct CancelOnCall(CancelToken);
+
+        impl ToolDispatcher for CancelOnCall {
+            fn definitions(&self) -> Vec<ToolDefinition> {
+                Vec::new()
+            }
+
+            fn dispatch(&mut self, _call: &ToolCall) -> ToolOutput {
+                self.0.cancel();
+                ToolOutput::error("Cancelled")
+            }
+        }
+
+        let cancel = CancelToken::new();
+        let mut provider = Scripted::new(vec![read(1), answer("Done.", 1)]);
+        let mut tools = CancelOnCall(cancel.clone());
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut tools, 10)
+            .with_cancel(cancel)
+            .run(&mut session);
+
+        assert_eq!(session.summary.outcome, RunOutcome::Cancelled);
+        assert_eq!(session.summary.iterations, 1);
+        assert_eq!(provider.sent.len(), 1);
+        // The tool call is still answered, so the session can be resumed.
+        assert_eq!(session.messages.len(), 3);
+    }
+
+    #[test]
+    fn token_budget_stops_the_run_before_the_next_call() {
+        let mut provider = Scripted::new(vec![read(20), read(20), answer("Done.", 1)]);
+        let mut tools = tools(3);
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut tools, 10)
+            .with_token_budget(50)
+            .run(&mut session);
+
+        let summary = &session.summary;
+        assert_eq!(summary.outcome, RunOutcome::BudgetExhausted);
+        assert_eq!(summary.iterations, 2);
+        assert_eq!(
+            summary.budget,
+            Some(TokenBudget {
+                limit: 50,
+                used: 60
+            })
+        );
+        // The call that crossed the budget still had its tool calls run.
+        assert_eq!(session.messages.len(), 5);
+        assert!(summary
+            .render()
+            .contains("Outcome: stopped (token budget exhausted)\n"));
+        assert!(summary
+            .render()
+            .contains("Token budget: 60 of 50 used (0 remaining)\n"));
+    }
+
+    #[test]
+    fn cost_cap_stops_the_run_before_a_call_that_could_exceed_it() {
+        let mut provider = Scripted::new((1..=5).map(read).collect());
+        let mut tools = tools(3);
+        let mut session = Session::new("Read the notes", &Settings::default());
+        // Output costs $0.001 a token; input is free.
+        let price = Price {
+            input: 0.0,
+            output: 1000.0,
+            cache_read: 0.0,
+            cache_write: 0.0,
+        };
+
+        AgentLoop::new(&mut provider, &mut tools, 10)
+            .with_price(price)
+            .with_cost_cap(1.015, 1000)
+            .run(&mut session);
+
+        // Each reply costs $0.01; a third call could cost $1 more.
+        let summary = &session.summary;
+        assert_eq!(summary.outcome, RunOutcome::CostCapReached);
+        assert_eq!(summary.iterations, 2);
+        assert!((summary.cost_usd.unwrap() - 0.02).abs() < 1e-9);
+        assert!((summary.cost_cap.unwrap().spent_usd - 0.02).abs() < 1e-9);
+        assert_eq!(session.replies[1].cost_usd, Some(0.01));
+    }
+
+    #[test]
+    fn deadline_stops_the_run_and_skips_the_remaining_calls() {
+        struct Slow;
+
+        impl ToolDispatcher for Slow {
+            fn definitions(&self) -> Vec<ToolDefinition> {
+                Vec::new()
+            }
+
+            fn dispatch(&mut self, _call: &ToolCall) -> ToolOutput {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                ToolOutput::ok("slow")
+            }
+        }
+
+        let mut two_calls = read(1);
+        two_calls.content.push(ContentBlock::ToolUse {
+            id: "call_second".to_string(),
+            name: "read".to_string(),
+            input: json!({}),
+        });
+        let mut provider = Scripted::new(vec![two_calls, answer("Done.", 1)]);
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut Slow, 10)
+            .with_deadline(Instant::now() + std::time::Duration::from_millis(50))
+            .run(&mut session);
+
+        assert_eq!(session.summary.outcome, RunOutcome::TimedOut);
+        assert_eq!(session.summary.iterations, 1);
+        let results = &session.messages[2].content;
+        assert_eq!(
+            results[1],
+            ContentBlock::ToolResult {
+                tool_use_id: "call_second".to_string(),
+                content: "Run cancelled before this tool call".to_string(),
+                is_error: true,
+            }
+        );
+        assert!(session.summary.duration_ms >= 200);
+    }
+
+    #[test]
+    fn run_reports_progress_to_the_event_sink() {
+        let collect = Collect::default();
+        let mut provider = Scripted::new(vec![read(5), answer("Done.", 7)]);
+        let mut tools = tools(3);
+        let mut session = Session::new("Read the notes", &Settings::default());
+        let events = Events::new(Some(Box::new(collect.clone())), &session.id);
+
+        AgentLoop::new(&mut provider, &mut tools, 10)
+            .with_events(events)
+            .run(&mut session);
+
+        let events = collect.0.borrow();
+        let types: Vec<&str> = events
+            .iter()
+            .map(|event| event["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "run_started",
+                "usage",
+                "tool_call_started",
+                "tool_call_finished",
+                "text_delta",
+                "usage",
+                "run_finished",
+            ]
+        );
+        assert!(events.iter().all(|event| event["session"] == session.id));
+        assert_eq!(events[3]["output"], "xxx");
+        assert_eq!(events[4]["text"], "Done.");
+        assert_eq!(events[5]["total"]["input_tokens"], 12);
+        assert_eq!(events[6]["summary"]["outcome"], "completed");
+    }
+
+    #[test]
+    fn long_conversations_are_compacted_and_recorded() {
+        let mut replies: Vec<_> = (1..=7).map(read).collect();
+        replies.push(answer("Done.", 1));
+        let mut provider = Scripted::new(replies);
+        let mut tools = tools(4000);
+        let mut session = Session::new("Read the notes", &Settings::default());
+
+        AgentLoop::new(&mut provider, &mut tools, 20)
+            .with_token_budget(1_000_000)
+            .with_compaction(CompactStrategy::Summarize, 3_000)
+            .run(&mut session);
+
+        assert_eq!(session.summary.outcome, RunOutcome::Completed);
+        assert!(provider.summaries > 0);
+        assert_eq!(session.compactions.len(), provider.summaries);
+        let first = &session.compactions[0];
+        assert_eq!(first.end, 3);
+        assert_eq!(first.summary, "Read the first files; nothing failed.");
+        assert!(first.tokens_after < first.tokens_before);
+        assert_eq!(first.usage.input_tokens, 50);
+        // The transcript keeps every message; the model is sent fewer.
+        assert_eq!(session.messages.len(), 16);
+        assert!(provider.sent.last().unwrap() < &session.messages.len());
+        let agent_input: u64 = (1..=7).sum::<u64>() + 1;
+        let summary_input = 50 * provider.summaries as u64;
+        assert_eq!(
+            session.summary.usage.input_tokens,
+            agent_input + summary_input
+        );
+    }
+
+    #[test]
+    fn summarizing_call_is_checked_against_the_cost_cap() {
+        // The fifth reply leaves too little of the cap for a summary.
+        let mut replies: Vec<_> = (1..=4).map(read).collect();
+        replies.push(read(999_990));
+        replies.push(answer("Done.", 1));
+        let mut provider = Scripted::new(replies);
+        let mut tools = tools(4000);
+        let mut session = Session::new("Read the notes", &Settings::default());
+        let price = Price {
+            input: 1.0,
+            output: 0.0,
+            cache_read: 0.0,
+            cache_write: 0.0,
+        };
+
+        AgentLoop::new(&mut provider, &mut tools, 20)
+            .with_price(price)
+            .with_cost_cap(1.0, 0)
+            .with_compaction(CompactStrategy::Summarize, 3_000)
+            .run(&mut session);
+
+        assert_eq!(session.summary.outcome, RunOutcome::CostCapReached);
+        assert_eq!(provider.summaries, 0);
+        assert!(session.compactions.is_empty());
+        assert_eq!(session.summary.iterations, 5);
+    }
+}
@@ -0,0 +1,305 @@
+//! Approval of tool calls.
+//!
+//! Every call is first checked against the `[[policy]]` rules; only calls
+//! the policy answers with `ask` reach the user.
+//!
+//! The plugin runs inside the host's `run_command` call and owns no
+//! terminal of its own, and lib_plugin_abi has no way to ask the user, so
+//! prompts go to `/dev/tty` unless a host offers the proposed prompt
+//! service:
+//!
+//! - `adi.host.prompt` is a proposed contract, not a service any host
+//!   offers yet. If a service is registered under that ID, the gate calls
+//!   `select` with `{"message", "options": [{"id", "label"}]}` and expects
+//!   `{"id": "<option id>"}` back. A denial is followed by `input` with
+//!   `{"message"}`, answered by `{"text": "<reason>"}`. Any answer other
+//!   than an option id denies the call; only `abort` ends the run.
+//! - Otherwise the prompt is written to and read from `/dev/tty`.
+//! - Without either, calls are denied. Pass `--yes` or set
+//!   `approval_policy` for unattended runs.
+//!
+//! Runs started through the agent service are unattended: calls the policy
+//! asks about are denied unless the run was started with `auto_approve`.
+
+use crate::cancel::CancelToken;
+use crate::config::PolicyAction;
+use crate::events::{Event, Events};
+use crate::message::ToolCall;
+use crate::policy::Policy;
+use crate::tools::Tool;
+use lib_plugin_abi::ServiceHandle;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+/// Proposed host service that would ask the user; see the module docs.
+pub const SERVICE_PROMPT: &str = "adi.host.prompt";
+
+/// A tool call waiting for approval.
+pub struct ApprovalRequest<'a> {
+    pub tool: &'a str,
+    pub input: &'a Value,
+    /// Diff or other preview of what the call will change.
+    pub preview: Option<String>,
+}
+
+impl ApprovalRequest<'_> {
+    /// Text shown to the user.
+    pub fn message(&self) -> String {
+        let input = serde_json::to_string_pretty(self.input).unwrap_or_default();
+        let mut message = format!("Allow tool call: {}\n\n{}\n", self.tool, input);
+        if let Some(preview) = &self.preview {
+            message.push_str(&format!("\n{}\n", preview.trim_end()));
+        }
+        message
+    }
+}
+
+/// The user's answer to an approval prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Run this call.
+    Once,
+    /// Run this and every later call of the same tool.
+    Session,
+    /// Skip the call; the reason is sent back to the model.
+    Deny(String),
+    /// Stop the run.
+    Abort,
+}
+
+/// Asks the user to decide on a tool call.
+pub trait Prompter {
+    fn prompt(&mut self, request: &ApprovalRequest) -> Result<Decision, String>;
+}
+
+/// Pick a service registered as `adi.host.prompt` if there is one, the
+/// terminal otherwise.
+pub fn prompter(host_prompt: Option<ServiceHandle>) -> Box<dyn Prompter> {
+    match host_prompt {
+        Some(handle) => Box::new(HostPrompter { handle }),
+        None => Box::new(TerminalPrompter),
+    }
+}
+
+/// Deny every call that would need a prompt, for runs nobody watches.
+pub fn unattended() -> Box<dyn Prompter> {
+    Box::new(Unattended)
+}
+
+/// Checks tool calls against the permission policy before they run.
+pub struct ApprovalGate {
+    policy: Policy,
+    /// Approve calls the policy would ask about (`--yes`).
+    auto_approve: bool,
+    prompter: Box<dyn Prompter>,
+    /// Tools approved for the rest of the session.
+    approved: HashSet<String>,
+    cancel: CancelToken,
+    events: Events,
+}
+
+impl ApprovalGate {
+    /// `cancel` is fired when the user aborts the run.
+    pub fn new(policy: Policy, prompter: Box<dyn Prompter>, cancel: CancelToken) -> Self {
+        Self {
+            policy,
+            auto_approve: false,
+            prompter,
+            approved: HashSet::new(),
+            cancel,
+            events: Events::default(),
+        }
+    }
+
+    /// Run calls that would otherwise be asked about without prompting.
+    pub fn with_auto_approve(mut self, auto_approve: bool) -> Self {
+        self.auto_approve = auto_approve;
+        self
+    }
+
+    /// Report prompts and answers to `events`.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// `Ok` if `call` of `tool` may run, otherwise the message returned to the model.
+    pub fn check(&mut self, tool: &dyn Tool, call: &ToolCall) -> Result<(), String> {
+        let verdict = self
+            .policy
+            .evaluate(&call.name, tool.read_only(), &call.input);
+        match verdict.action {
+            PolicyAction::Allow => return Ok(()),
+            PolicyAction::Deny => {
+                let source = match &verdict.rule {
+                    Some(rule) => format!("policy rule {}", rule),
+                    None => "approval_policy = deny".to_string(),
+                };
+                return Err(match verdict.reason {
+                    Some(reason) => format!("Tool call denied by {}: {}", source, reason),
+                    None => format!("Tool call denied by {}", source),
+                });
+            }
+            PolicyAction::Ask => {}
+        }
+        if self.auto_approve || self.approved.contains(&call.name) {
+            return Ok(());
+        }
+
+        let request = ApprovalRequest {
+            tool: &call.name,
+            input: &call.input,
+            preview: tool.preview(&call.input),
+        };
+        self.events.emit(Event::ApprovalRequested {
+            tool: request.tool,
+            input: request.input,
+            preview: request.preview.as_deref(),
+        });
+        let decision = self.prompter.prompt(&request);
+        self.events.emit(Event::ApprovalResolved {
+            tool: request.tool,
+            decision: match &decision {
+                Ok(Decision::Once) => "once",
+                Ok(Decision::Session) => "session",
+                Ok(Decision::Deny(_)) | Err(_) => "deny",
+                Ok(Decision::Abort) => "abort",
+            },
+        });
+        match decision.map_err(|e| format!("Tool call denied: {}", e))? {
+            Decision::Once => Ok(()),
+            Decision::Session => {
+                self.approved.insert(call.name.clone());
+                Ok(())
+            }
+            Decision::Deny(reason) if reason.trim().is_empty() => {
+                Err("The user denied this tool call".to_string())
+            }
+            Decision::Deny(reason) => {
+                Err(format!("The user denied this tool call: {}", reason.trim()))
+            }
+            Decision::Abort => {
+                self.cancel.cancel();
+                Err("The user aborted the run".to_string())
+            }
+        }
+    }
+}
+
+// === Host prompt ===
+
+struct HostPrompter {
+    handle: ServiceHandle,
+}
+
+impl HostPrompter {
+    fn invoke(&self, method: &str, args: Value) -> Result<Value, String> {
+        let response = unsafe { self.handle.invoke(method, &args.to_string()) }
+            .map_err(|e| format!("{} {} failed: {}", SERVICE_PROMPT, method, e.message))?;
+        serde_json::from_str(&response)
+            .map_err(|e| format!("Invalid {} response: {}", SERVICE_PROMPT, e))
+    }
+}
+
+impl Prompter for HostPrompter {
+    fn prompt(&mut self, request: &ApprovalRequest) -> Result<Decision, String> {
+        let options = json!([
+            {"id": "approve", "label": "Approve once"},
+            {"id": "session", "label": format!("Approve {} for this session", request.tool)},
+            {"id": "deny", "label": "Deny"},
+            {"id": "abort", "label": "Abort the run"}
+        ]);
+        let choice = self.invoke(
+            "select",
+            json!({"message": request.message(), "options": options}),
+        )?;
+        match choice["id"].as_str() {
+            Some("approve") => Ok(Decision::Once),
+            Some("session") => Ok(Decision::Session),
+            Some("deny") => {
+                let reason = self.invoke(
+                    "input",
+                    json!({"message": "Reason for denying (sent to the model)"}),
+                )?;
+                Ok(Decision::Deny(
+                    reason["text"].as_str().unwrap_or_default().to_string(),
+                ))
+            }
+            Some("abort") => Ok(Decision::Abort),
+            // A dismissed prompt must never approve the call, but only an
+            // explicit abort ends the run.
+            _ => Ok(Decision::Deny(
+                "the approval prompt was dismissed".to_string(),
+            )),
+        }
+    }
+}
+
+// === Terminal prompt ===
+
+struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn prompt(&mut self, request: &ApprovalRequest) -> Result<Decision, String> {
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|_| {
+                "no terminal to ask for approval; re-run with --yes or set approval_policy"
+                    .to_string()
+            })?;
+        let mut reader = BufReader::new(tty.try_clone().map_err(|e| e.to_string())?);
+        let mut tty = tty;
+
+        write_tty(&mut tty, &format!("\n{}\n", request.message()))?;
+        loop {
+            write_tty(
+                &mut tty,
+                "[y] approve once  [a] approve for this session  [n] deny  [q] abort: ",
+            )?;
+            match read_line(&mut reader)?.to_lowercase().as_str() {
+                "y" | "yes" => return Ok(Decision::Once),
+                "a" | "always" => return Ok(Decision::Session),
+                "n" | "no" => {
+                    write_tty(&mut tty, "Reason (sent to the model, optional): ")?;
+                    return Ok(Decision::Deny(read_line(&mut reader)?));
+                }
+                "q" | "quit" | "abort" => return Ok(Decision::Abort),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn write_tty(tty: &mut File, text: &str) -> Result<(), String> {
+    tty.write_all(text.as_bytes())
+        .and_then(|_| tty.flush())
+        .map_err(|e| format!("Failed to write to terminal: {}", e))
+}
+
+fn read_line(reader: &mut BufReader<File>) -> Result<String, String> {
+    let mut line = String::new();
+    let n = reader
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read from terminal: {}", e))?;
+    if n == 0 {
+        return Err("terminal closed while asking for approval".to_string());
+    }
+    Ok(line.trim().to_string())
+}
+
+// === Unattended ===
+
+struct Unattended;
+
+impl Prompter for Unattended {
+    fn prompt(&mut self, _request: &ApprovalRequest) -> Result<Decision, String> {
+        Err(
+            "nobody to ask for approval; start the run with auto_approve or set approval_policy"
+                .to_string(),
+        )
+    }
+}
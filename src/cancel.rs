@@ -0,0 +1,87 @@
+//! Cooperative cancellation of agent runs.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Instant;
+
+/// Tokens of runs still in flight, cancelled together on plugin cleanup.
+static LIVE_TOKENS: Mutex<Vec<Weak<AtomicBool>>> = Mutex::new(Vec::new());
+
+/// Shared flag checked by the loop and by running subprocesses.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        let flag = Arc::new(AtomicBool::new(false));
+        if let Ok(mut live) = LIVE_TOKENS.lock() {
+            live.retain(|token| token.strong_count() > 0);
+            live.push(Arc::downgrade(&flag));
+        }
+        Self { flag }
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Cancel this token at `deadline` unless the returned watchdog is dropped first.
+    pub fn cancel_at(&self, deadline: Instant) -> Watchdog {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let token = self.clone();
+        thread::spawn(move || {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(wait) {
+                token.cancel();
+            }
+        });
+        Watchdog { _stop: stop }
+    }
+}
+
+/// Pending deadline of a token; dropping it disarms the deadline.
+pub struct Watchdog {
+    _stop: mpsc::Sender<()>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cancel every run that is still in flight.
+pub fn cancel_all() {
+    if let Ok(mut live) = LIVE_TOKENS.lock() {
+        for flag in live.drain(..).filter_map(|token| token.upgrade()) {
+            CancelToken { flag }.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn deadline_cancels_unless_the_watchdog_is_dropped() {
+        let token = CancelToken::new();
+        let watchdog = token.cancel_at(Instant::now() + Duration::from_millis(20));
+        drop(watchdog);
+        thread::sleep(Duration::from_millis(60));
+        assert!(!token.is_cancelled());
+
+        let _watchdog = token.cancel_at(Instant::now() + Duration::from_millis(20));
+        thread::sleep(Duration::from_millis(60));
+        assert!(token.is_cancelled());
+    }
+}
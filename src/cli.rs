@@ -0,0 +1,921 @@
+//! Command-line arguments.
+//!
+//! Each command declares its positional arguments and options once in
+//! [`COMMANDS`]; the same spec validates an invocation, renders the
+//! command's `--help` and fills in `list_commands`.
+//!
+//! Options are accepted as `--key value`, `--key=value`, `-k value` or
+//! grouped short flags such as `-yh`. A value that starts with `-` must be
+//! attached with `=` unless it is a number, and everything after `--` is
+//! positional. Global options such as `--output` may also come before the
+//! command name.
+
+use crate::config::schema::{KeyType, SETTINGS};
+use crate::suggest;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+/// A positional argument.
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+/// An option: a flag such as `--yes`, or `--format <fmt>` when it has a value.
+pub struct OptionSpec {
+    pub long: &'static str,
+    pub short: Option<char>,
+    /// Placeholder for the value; `None` for flags.
+    pub value: Option<&'static str>,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+/// A command, or a subcommand of one.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub args: &'static [ArgSpec],
+    pub options: &'static [OptionSpec],
+    /// Accept every setting as an option, e.g. `--max-iterations <n>`.
+    pub settings: bool,
+    pub subcommands: &'static [CommandSpec],
+    /// Subcommand run when none is given.
+    pub default: Option<&'static str>,
+}
+
+const fn arg(name: &'static str, description: &'static str) -> ArgSpec {
+    ArgSpec {
+        name,
+        required: true,
+        description,
+    }
+}
+
+const fn optional(name: &'static str, description: &'static str) -> ArgSpec {
+    ArgSpec {
+        name,
+        required: false,
+        description,
+    }
+}
+
+const fn flag(long: &'static str, description: &'static str) -> OptionSpec {
+    OptionSpec {
+        long,
+        short: None,
+        value: None,
+        required: false,
+        description,
+    }
+}
+
+const fn option(long: &'static str, value: &'static str, description: &'static str) -> OptionSpec {
+    OptionSpec {
+        long,
+        short: None,
+        value: Some(value),
+        required: false,
+        description,
+    }
+}
+
+impl OptionSpec {
+    const fn short(mut self, short: char) -> Self {
+        self.short = Some(short);
+        self
+    }
+
+    const fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+
+const fn command(name: &'static str, description: &'static str) -> CommandSpec {
+    CommandSpec {
+        name,
+        description,
+        args: &[],
+        options: &[],
+        settings: false,
+        subcommands: &[],
+        default: None,
+    }
+}
+
+/// Top-level commands, in help order.
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        args: &[optional(
+            "task",
+            "Task for the agent; with --resume, an optional follow-up message",
+        )],
+        options: &[
+            option("resume", "<id>", "Continue a saved session"),
+            option(
+                "timeout",
+                "<duration>",
+                "Wall-clock limit for the run, e.g. 90s or 30m",
+            ),
+            flag("yes", "Approve every tool call without asking").short('y'),
+            flag("stream", "Write run events to stderr as JSON lines"),
+        ],
+        settings: true,
+        ..command("run", "Run agent with a task")
+    },
+    CommandSpec {
+        subcommands: &[
+            CommandSpec {
+                options: &[flag(
+                    "origin",
+                    "Show the file or variable that set each value",
+                )],
+                ..command("show", "Show the resolved configuration")
+            },
+            CommandSpec {
+                args: &[
+                    arg("key", "Setting to change (see 'config show')"),
+                    arg("value", "New value"),
+                ],
+                options: &[flag(
+                    "project",
+                    "Write to the project config instead of the user config",
+                )],
+                ..command("set", "Set a value in a config file")
+            },
+            CommandSpec {
+                args: &[arg("key", "Setting to remove (see 'config show')")],
+                options: &[flag(
+                    "project",
+                    "Remove from the project config instead of the user config",
+                )],
+                ..command("unset", "Remove a value from a config file")
+            },
+            CommandSpec {
+                args: &[optional(
+                    "path",
+                    "Config file to check; defaults to the user and project configs",
+                )],
+                ..command("validate", "Check config files against the schema")
+            },
+            CommandSpec {
+                subcommands: &[CommandSpec {
+                    args: &[
+                        arg("tool", "Tool name"),
+                        optional("json-args", "Tool arguments as a JSON object"),
+                    ],
+                    ..command("test", "Show how a tool call would be approved")
+                }],
+                ..command("policy", "Check approval policy rules")
+            },
+        ],
+        default: Some("show"),
+        ..command("config", "Manage configuration")
+    },
+    CommandSpec {
+        subcommands: &[command("list", "List available tools")],
+        default: Some("list"),
+        ..command("tools", "List available tools")
+    },
+    CommandSpec {
+        subcommands: &[
+            CommandSpec {
+                options: &[flag("tree", "Show forks under the session they came from")],
+                ..command("list", "List saved sessions")
+            },
+            CommandSpec {
+                args: &[arg("id", "Session to show")],
+                ..command("show", "Show a session and its transcript")
+            },
+            CommandSpec {
+                args: &[arg("id", "Session to fork")],
+                options: &[
+                    option("at", "<turn>", "Last turn kept in the fork").required(),
+                    option("message", "<text>", "Replace the user message of that turn").short('m'),
+                ],
+                ..command("fork", "Branch a session at a turn")
+            },
+            CommandSpec {
+                args: &[arg("id", "Session to export")],
+                options: &[
+                    option("format", "<md|json|html>", "Output format (default: md)").short('f'),
+                ],
+                ..command("export", "Render a session as Markdown, JSON or HTML")
+            },
+            command("cost", "Report spend by day and model"),
+        ],
+        default: Some("list"),
+        ..command("sessions", "List and show saved sessions")
+    },
+];
+
+const ROOT: CommandSpec = CommandSpec {
+    subcommands: COMMANDS,
+    ..command("", "")
+};
+
+/// Options of every command, also accepted before the command name.
+const GLOBAL_OPTIONS: &[OptionSpec] = &[option(
+    "output",
+    "<text|json>",
+    "Output format (default: text)",
+)];
+
+// === Parsing ===
+
+/// Result of parsing a command line.
+pub enum Parsed {
+    /// `--help` was given, or a command that needs a subcommand had none.
+    Help(String),
+    Command(Invocation),
+}
+
+/// A validated command line.
+#[derive(Debug)]
+pub struct Invocation {
+    /// Command and subcommand names, e.g. `["sessions", "fork"]`.
+    pub command: Vec<&'static str>,
+    pub args: Vec<String>,
+    /// Options by long name: strings for values, `true` for flags.
+    pub options: Value,
+}
+
+/// Why a command line was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgError {
+    UnknownCommand {
+        parent: String,
+        name: String,
+        choices: Vec<&'static str>,
+    },
+    UnknownOption {
+        command: String,
+        option: String,
+        suggestion: Option<String>,
+    },
+    MissingValue {
+        command: String,
+        option: String,
+    },
+    UnexpectedValue {
+        command: String,
+        option: String,
+    },
+    MissingOption {
+        command: String,
+        option: String,
+    },
+    MissingArgument {
+        command: String,
+        name: &'static str,
+    },
+    UnexpectedArgument {
+        command: String,
+        value: String,
+    },
+}
+
+impl ArgError {
+    /// Stable error code for `--output json`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownCommand { .. } => "unknown_command",
+            Self::UnknownOption { .. } => "unknown_option",
+            Self::MissingValue { .. } => "missing_value",
+            Self::UnexpectedValue { .. } => "unexpected_value",
+            Self::MissingOption { .. } => "missing_option",
+            Self::MissingArgument { .. } => "missing_argument",
+            Self::UnexpectedArgument { .. } => "unexpected_argument",
+        }
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = match self {
+            Self::UnknownCommand {
+                parent,
+                name,
+                choices,
+            } => {
+                let message = if parent.is_empty() {
+                    format!("Unknown command: {}", name)
+                } else {
+                    format!("Unknown {} subcommand: {}", parent, name)
+                };
+                let message = suggest::with_suggestion(message, name, choices.iter().copied());
+                return write!(f, "{}. Use {}", message, quoted_list(choices));
+            }
+            Self::UnknownOption {
+                command,
+                option,
+                suggestion,
+            } => {
+                write!(f, "Unknown option {} for '{}'", option, command)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, " (did you mean '{}'?)", suggestion)?;
+                }
+                command
+            }
+            Self::MissingValue { command, option } => {
+                write!(f, "Option {} expects a value", option)?;
+                command
+            }
+            Self::UnexpectedValue { command, option } => {
+                write!(f, "Option {} does not take a value", option)?;
+                command
+            }
+            Self::MissingOption { command, option } => {
+                write!(f, "Missing required option {}", option)?;
+                command
+            }
+            Self::MissingArgument { command, name } => {
+                write!(f, "Missing argument <{}>", name)?;
+                command
+            }
+            Self::UnexpectedArgument { command, value } => {
+                write!(f, "Unexpected argument '{}'", value)?;
+                if command == "run" {
+                    write!(f, " (quote a task that has several words)")?;
+                }
+                command
+            }
+        };
+        write!(f, ". Run '{} --help' for usage", command)
+    }
+}
+
+/// `'a', 'b' or 'c'`.
+fn quoted_list(items: &[&str]) -> String {
+    let quoted: Vec<String> = items.iter().map(|item| format!("'{}'", item)).collect();
+    match quoted.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        Some((last, _)) => last.clone(),
+        None => String::new(),
+    }
+}
+
+/// An option of a command, including settings and `--help`.
+struct Flag {
+    long: String,
+    short: Option<char>,
+    value: Option<String>,
+    required: bool,
+    description: String,
+}
+
+impl Flag {
+    /// `-m, --message <text>`.
+    fn label(&self) -> String {
+        let short = match self.short {
+            Some(short) => format!("-{}, ", short),
+            None => "    ".to_string(),
+        };
+        match &self.value {
+            Some(value) => format!("{}--{} {}", short, self.long, value),
+            None => format!("{}--{}", short, self.long),
+        }
+    }
+}
+
+impl From<&OptionSpec> for Flag {
+    fn from(option: &OptionSpec) -> Self {
+        Self {
+            long: option.long.to_string(),
+            short: option.short,
+            value: option.value.map(str::to_string),
+            required: option.required,
+            description: option.description.to_string(),
+        }
+    }
+}
+
+fn flags(spec: &CommandSpec) -> Vec<Flag> {
+    let mut flags: Vec<Flag> = spec.options.iter().map(Flag::from).collect();
+    if spec.settings {
+        flags.extend(SETTINGS.iter().map(|setting| Flag {
+            long: setting.key.replace('_', "-"),
+            short: None,
+            value: Some(placeholder(setting.ty)),
+            required: false,
+            description: setting.description.to_string(),
+        }));
+    }
+    flags.extend(GLOBAL_OPTIONS.iter().map(Flag::from));
+    flags.push(Flag {
+        long: "help".to_string(),
+        short: Some('h'),
+        value: None,
+        required: false,
+        description: "Show this help".to_string(),
+    });
+    flags
+}
+
+fn placeholder(ty: KeyType) -> String {
+    match ty {
+        KeyType::Integer { .. } => "<n>".to_string(),
+        KeyType::Amount => "<amount>".to_string(),
+        KeyType::Url => "<url>".to_string(),
+        KeyType::Choice(choices) => format!("<{}>", choices.join("|")),
+        _ => "<value>".to_string(),
+    }
+}
+
+/// Whether `token` is an option rather than a value. Negative numbers and a
+/// lone `-` are values.
+fn is_option(token: &str) -> bool {
+    match token.strip_prefix('-') {
+        Some(rest) => {
+            !rest.is_empty() && !rest.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        }
+        None => false,
+    }
+}
+
+/// Value of `--output` in `args`, found without parsing them so that
+/// argument errors can be reported in the requested format.
+pub fn output_option(args: &[String]) -> Option<&str> {
+    let mut tokens = args.iter().take_while(|token| *token != "--");
+    let mut found = None;
+    while let Some(token) = tokens.next() {
+        if token == "--output" {
+            found = tokens.next().map(String::as_str);
+        } else if let Some(value) = token.strip_prefix("--output=") {
+            found = Some(value);
+        }
+    }
+    found
+}
+
+/// Number of leading tokens of `args` that are global options and their
+/// values, e.g. 2 for `--output json run task`.
+pub fn global_prefix(args: &[String]) -> usize {
+    let mut count = 0;
+    while let Some(token) = args.get(count) {
+        let Some(long) = token.strip_prefix("--") else {
+            break;
+        };
+        let (name, attached) = match long.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (long, false),
+        };
+        let Some(option) = GLOBAL_OPTIONS.iter().find(|option| option.long == name) else {
+            break;
+        };
+        count += 1;
+        let takes_next = option.value.is_some() && !attached;
+        if takes_next && args.get(count).is_some_and(|value| !is_option(value)) {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Parse `args`, the command name first unless global options precede it.
+pub fn parse(args: &[String]) -> Result<Parsed, ArgError> {
+    let mut spec = &ROOT;
+    let mut path: Vec<&'static str> = Vec::new();
+    let mut rest = args;
+    // Global options given before a command name, parsed with its options.
+    let mut leading: Vec<String> = Vec::new();
+
+    while !spec.subcommands.is_empty() {
+        let skip = global_prefix(rest);
+        leading.extend_from_slice(&rest[..skip]);
+        rest = &rest[skip..];
+        let name = match rest.first() {
+            // `sessions --help` describes `sessions`, not its default subcommand.
+            Some(token) if token == "--help" || token == "-h" => {
+                return Ok(Parsed::Help(help(&path, spec)));
+            }
+            Some(token) if !is_option(token) => {
+                rest = &rest[1..];
+                token.as_str()
+            }
+            _ => match spec.default {
+                Some(default) => default,
+                None => return Ok(Parsed::Help(help(&path, spec))),
+            },
+        };
+        let Some(sub) = spec.subcommands.iter().find(|sub| sub.name == name) else {
+            return Err(ArgError::UnknownCommand {
+                parent: path.join(" "),
+                name: name.to_string(),
+                choices: spec.subcommands.iter().map(|sub| sub.name).collect(),
+            });
+        };
+        spec = sub;
+        path.push(sub.name);
+    }
+
+    let command = path.join(" ");
+    let flags = flags(spec);
+    let mut options = Map::new();
+    let mut positional = Vec::new();
+    let mut tokens = leading.iter().chain(rest);
+    let mut only_positional = false;
+
+    while let Some(token) = tokens.next() {
+        if only_positional || !is_option(token) {
+            positional.push(token.clone());
+            continue;
+        }
+        if token == "--" {
+            only_positional = true;
+            continue;
+        }
+
+        // Resolve `--name[=value]` or a group of short flags to options with
+        // an optional attached value.
+        let mut found: Vec<(&Flag, String, Option<String>)> = Vec::new();
+        if let Some(long) = token.strip_prefix("--") {
+            let (name, attached) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let Some(flag) = flags.iter().find(|flag| flag.long == name) else {
+                return Err(ArgError::UnknownOption {
+                    command,
+                    option: format!("--{}", name),
+                    suggestion: suggest::closest(name, flags.iter().map(|f| f.long.as_str()))
+                        .map(|long| format!("--{}", long)),
+                });
+            };
+            found.push((flag, format!("--{}", name), attached));
+        } else {
+            let group: Vec<char> = token[1..].chars().collect();
+            for (index, short) in group.iter().enumerate() {
+                let Some(flag) = flags.iter().find(|flag| flag.short == Some(*short)) else {
+                    return Err(ArgError::UnknownOption {
+                        command,
+                        option: format!("-{}", short),
+                        suggestion: None,
+                    });
+                };
+                if flag.value.is_some() && index + 1 < group.len() {
+                    // `-fjson` or `-f=json`.
+                    let attached: String = group[index + 1..].iter().collect();
+                    let attached = attached.strip_prefix('=').unwrap_or(&attached);
+                    found.push((flag, format!("-{}", short), Some(attached.to_string())));
+                    break;
+                }
+                found.push((flag, format!("-{}", short), None));
+            }
+        }
+
+        for (flag, written, attached) in found {
+            if flag.long == "help" {
+                return Ok(Parsed::Help(help(&path, spec)));
+            }
+            let value = match (&flag.value, attached) {
+                (None, None) => json!(true),
+                (None, Some(_)) => {
+                    return Err(ArgError::UnexpectedValue {
+                        command,
+                        option: written,
+                    })
+                }
+                (Some(_), Some(value)) => json!(value),
+                (Some(_), None) => match tokens.next() {
+                    Some(value) if !is_option(value) => json!(value),
+                    _ => {
+                        return Err(ArgError::MissingValue {
+                            command,
+                            option: written,
+                        })
+                    }
+                },
+            };
+            options.insert(flag.long.clone(), value);
+        }
+    }
+
+    if let Some(extra) = positional.get(spec.args.len()) {
+        return Err(ArgError::UnexpectedArgument {
+            command,
+            value: extra.clone(),
+        });
+    }
+    if let Some(missing) = spec.args.get(positional.len()).filter(|arg| arg.required) {
+        return Err(ArgError::MissingArgument {
+            command,
+            name: missing.name,
+        });
+    }
+    if let Some(missing) = flags
+        .iter()
+        .find(|flag| flag.required && !options.contains_key(&flag.long))
+    {
+        return Err(ArgError::MissingOption {
+            command,
+            option: format!("--{}", missing.long),
+        });
+    }
+
+    Ok(Parsed::Command(Invocation {
+        command: path,
+        args: positional,
+        options: Value::Object(options),
+    }))
+}
+
+// === Help ===
+
+/// One-line usage of a top-level command and its subcommands, for `list_commands`.
+pub fn usage(spec: &CommandSpec) -> String {
+    let mut parts = vec![spec.name.to_string()];
+    if !spec.subcommands.is_empty() {
+        let inner: Vec<String> = spec.subcommands.iter().map(usage).collect();
+        let inner = inner.join("|");
+        parts.push(match spec.default {
+            Some(_) => format!("[{}]", inner),
+            None if spec.subcommands.len() > 1 => format!("<{}>", inner),
+            None => inner,
+        });
+    }
+    parts.extend(spec.args.iter().map(arg_label));
+    for option in spec.options {
+        let label = match option.value {
+            Some(value) => format!("--{} {}", option.long, value),
+            None => format!("--{}", option.long),
+        };
+        parts.push(if option.required {
+            label
+        } else {
+            format!("[{}]", label)
+        });
+    }
+    if spec.settings {
+        parts.push("[--<setting> <value>]".to_string());
+    }
+    parts.join(" ")
+}
+
+fn arg_label(arg: &ArgSpec) -> String {
+    if arg.required {
+        format!("<{}>", arg.name)
+    } else {
+        format!("[{}]", arg.name)
+    }
+}
+
+/// `--help` output for the command at `path`.
+fn help(path: &[&str], spec: &CommandSpec) -> String {
+    if path.is_empty() {
+        let mut output = String::from(
+            "ADI Agent Loop - Autonomous LLM agent with tool execution\n\nCommands:\n",
+        );
+        push_rows(
+            &mut output,
+            COMMANDS
+                .iter()
+                .map(|c| (c.name.to_string(), c.description.to_string())),
+        );
+        output.push_str("\nGlobal options:\n");
+        push_rows(
+            &mut output,
+            GLOBAL_OPTIONS
+                .iter()
+                .map(|option| (Flag::from(option).label(), option.description.to_string())),
+        );
+        output.push_str(
+            "\nUsage: adi run adi.agent-loop [options] <command> [args]\n\
+             Run '<command> --help' for the options of a command.",
+        );
+        return output;
+    }
+
+    let command = path.join(" ");
+    // Global options are listed once, in the root help.
+    let flags: Vec<Flag> = flags(spec)
+        .into_iter()
+        .filter(|flag| !GLOBAL_OPTIONS.iter().any(|option| option.long == flag.long))
+        .collect();
+    let mut output = format!("Usage: {}", command);
+    if !spec.subcommands.is_empty() {
+        output.push_str(if spec.default.is_some() {
+            " [<command>]"
+        } else {
+            " <command>"
+        });
+    }
+    for arg in spec.args {
+        output.push(' ');
+        output.push_str(&arg_label(arg));
+    }
+    for flag in flags.iter().filter(|flag| flag.required) {
+        output.push_str(&format!(
+            " --{} {}",
+            flag.long,
+            flag.value.as_deref().unwrap_or("")
+        ));
+    }
+    if flags.iter().any(|flag| !flag.required) {
+        output.push_str(" [options]");
+    }
+    output.push_str(&format!("\n\n{}\n", spec.description));
+
+    if !spec.subcommands.is_empty() {
+        output.push_str("\nCommands:\n");
+        push_rows(
+            &mut output,
+            spec.subcommands.iter().map(|sub| {
+                let mut description = sub.description.to_string();
+                if spec.default == Some(sub.name) {
+                    description.push_str(" (default)");
+                }
+                (sub.name.to_string(), description)
+            }),
+        );
+    }
+    if !spec.args.is_empty() {
+        output.push_str("\nArguments:\n");
+        push_rows(
+            &mut output,
+            spec.args
+                .iter()
+                .map(|arg| (format!("<{}>", arg.name), arg.description.to_string())),
+        );
+    }
+    let (settings, options): (Vec<&Flag>, Vec<&Flag>) = flags.iter().partition(|flag| {
+        spec.settings
+            && SETTINGS
+                .iter()
+                .any(|s| s.key.replace('_', "-") == flag.long)
+    });
+    output.push_str("\nOptions:\n");
+    push_rows(
+        &mut output,
+        options
+            .iter()
+            .map(|flag| (flag.label(), flag.description.clone())),
+    );
+    if !settings.is_empty() {
+        output.push_str("\nSettings (override the config for this run):\n");
+        push_rows(
+            &mut output,
+            settings
+                .iter()
+                .map(|flag| (flag.label(), flag.description.clone())),
+        );
+    }
+    if !spec.subcommands.is_empty() {
+        output.push_str(&format!(
+            "\nRun '{} <command> --help' for the options of a command.\n",
+            command
+        ));
+    }
+    output.trim_end().to_string()
+}
+
+/// Append aligned `name  description` rows.
+fn push_rows(output: &mut String, rows: impl Iterator<Item = (String, String)>) {
+    let rows: Vec<(String, String)> = rows.collect();
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, description) in rows {
+        output.push_str(&format!(
+            "  {:<width$}  {}\n",
+            name,
+            description,
+            width = width
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Parsed, ArgError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse(&args)
+    }
+
+    fn invocation(args: &[&str]) -> Invocation {
+        match parse_args(args) {
+            Ok(Parsed::Command(invocation)) => invocation,
+            Ok(Parsed::Help(_)) => panic!("{:?} printed help", args),
+            Err(e) => panic!("{:?} failed: {}", args, e),
+        }
+    }
+
+    #[test]
+    fn option_values_are_not_taken_as_arguments() {
+        let run = invocation(&["run", "--max-iterations", "5", "fix tests"]);
+        assert_eq!(run.command, ["run"]);
+        assert_eq!(run.args, ["fix tests"]);
+        assert_eq!(run.options["max-iterations"], "5");
+
+        let run = invocation(&["run", "fix tests", "--resume", "abc"]);
+        assert_eq!(run.args, ["fix tests"]);
+        assert_eq!(run.options["resume"], "abc");
+    }
+
+    #[test]
+    fn attached_values() {
+        let run = invocation(&["run", "--max-iterations=5", "task"]);
+        assert_eq!(run.options["max-iterations"], "5");
+
+        let export = invocation(&["sessions", "export", "abc", "-fjson"]);
+        assert_eq!(export.options["format"], "json");
+        let export = invocation(&["sessions", "export", "abc", "-f=html"]);
+        assert_eq!(export.options["format"], "html");
+
+        let err = parse_args(&["run", "--yes=1", "task"]).err().unwrap();
+        assert_eq!(err.code(), "unexpected_value");
+    }
+
+    #[test]
+    fn short_flags() {
+        let run = invocation(&["run", "-y", "task"]);
+        assert_eq!(run.options["yes"], true);
+        assert_eq!(run.args, ["task"]);
+
+        assert!(matches!(parse_args(&["run", "-h"]), Ok(Parsed::Help(_))));
+        let err = parse_args(&["run", "-x", "task"]).err().unwrap();
+        assert_eq!(err.code(), "unknown_option");
+    }
+
+    #[test]
+    fn negative_numbers_are_values() {
+        let run = invocation(&["run", "--max-iterations", "-1", "task"]);
+        assert_eq!(run.options["max-iterations"], "-1");
+        let run = invocation(&["run", "--max-cost", "-.5", "task"]);
+        assert_eq!(run.options["max-cost"], "-.5");
+        let run = invocation(&["run", "-"]);
+        assert_eq!(run.args, ["-"]);
+
+        let err = parse_args(&["run", "--resume", "--yes"]).err().unwrap();
+        assert_eq!(
+            err,
+            ArgError::MissingValue {
+                command: "run".to_string(),
+                option: "--resume".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let run = invocation(&["run", "-y", "--", "--explain this"]);
+        assert_eq!(run.options["yes"], true);
+        assert_eq!(run.args, ["--explain this"]);
+
+        let err = parse_args(&["run", "--", "a", "b"]).err().unwrap();
+        assert_eq!(err.code(), "unexpected_argument");
+    }
+
+    #[test]
+    fn output_is_a_global_option() {
+        let args: Vec<String> = ["--output", "json", "run", "task"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(global_prefix(&args), 2);
+        assert_eq!(output_option(&args), Some("json"));
+
+        let run = invocation(&["--output", "json", "run", "task"]);
+        assert_eq!(run.command, ["run"]);
+        assert_eq!(run.args, ["task"]);
+        assert_eq!(run.options["output"], "json");
+
+        let list = invocation(&["--output=json", "sessions", "list"]);
+        assert_eq!(list.command, ["sessions", "list"]);
+        let list = invocation(&["sessions", "--output", "json"]);
+        assert_eq!(list.command, ["sessions", "list"]);
+        let run = invocation(&["run", "task", "--output", "json"]);
+        assert_eq!(run.options["output"], "json");
+
+        let Ok(Parsed::Help(root)) = parse_args(&["--help"]) else {
+            panic!("no root help");
+        };
+        assert_eq!(root.matches("--output").count(), 1, "{}", root);
+        let Ok(Parsed::Help(run)) = parse_args(&["run", "--help"]) else {
+            panic!("no run help");
+        };
+        assert!(!run.contains("--output"), "{}", run);
+    }
+
+    #[test]
+    fn unknown_options_suggest_the_closest_one() {
+        let err = parse_args(&["run", "--max-iteration", "5", "task"])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            ArgError::UnknownOption {
+                command: "run".to_string(),
+                option: "--max-iteration".to_string(),
+                suggestion: Some("--max-iterations".to_string()),
+            }
+        );
+        assert!(err.to_string().contains("did you mean '--max-iterations'?"));
+
+        let err = parse_args(&["sessions", "lst"]).err().unwrap();
+        assert_eq!(err.code(), "unknown_command");
+        assert!(err.to_string().contains("'list'"), "{}", err);
+    }
+}
@@ -0,0 +1,931 @@
+//! Agent settings and config files.
+
+pub mod schema;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// LLM backend used by the agent loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+    /// Anthropic Messages API.
+    Anthropic,
+    /// Any server exposing `/v1/chat/completions` (OpenAI, llama.cpp, vLLM, Ollama).
+    OpenAiCompatible,
+    /// Scripted turns read from `mock_script`, for offline runs.
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "anthropic" => Ok(Self::Anthropic),
+            "openai-compatible" => Ok(Self::OpenAiCompatible),
+            "mock" => Ok(Self::Mock),
+            _ => Err(format!(
+                "Unknown provider: {}. Use 'anthropic', 'openai-compatible' or 'mock'",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Anthropic => "anthropic",
+            Self::OpenAiCompatible => "openai-compatible",
+            Self::Mock => "mock",
+        }
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How side-effecting tool calls are approved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalPolicy {
+    /// Ask before each call unless `--yes` is given.
+    Ask,
+    /// Approve every call.
+    Auto,
+    /// Refuse every call.
+    Deny,
+}
+
+impl ApprovalPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "ask" => Ok(Self::Ask),
+            "auto" => Ok(Self::Auto),
+            "deny" => Ok(Self::Deny),
+            _ => Err(format!(
+                "Unknown approval policy: {}. Use 'ask', 'auto' or 'deny'",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ask => "ask",
+            Self::Auto => "auto",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+/// How older turns are compacted when the context grows past `compact_threshold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactStrategy {
+    /// Replace them with a summary written by the model.
+    Summarize,
+    /// Drop them.
+    Truncate,
+    /// Never compact.
+    Off,
+}
+
+impl CompactStrategy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "summarize" => Ok(Self::Summarize),
+            "truncate" => Ok(Self::Truncate),
+            "off" => Ok(Self::Off),
+            _ => Err(format!(
+                "Unknown compact strategy: {}. Use 'summarize', 'truncate' or 'off'",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Summarize => "summarize",
+            Self::Truncate => "truncate",
+            Self::Off => "off",
+        }
+    }
+}
+
+/// Whether tools of tool-provider services may be read-only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServiceReadOnly {
+    /// Ask about their calls like any side-effecting tool.
+    #[default]
+    Ignore,
+    /// Let a service mark its tools read-only, so their calls skip approval.
+    Trust,
+}
+
+impl ServiceReadOnly {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "ignore" => Ok(Self::Ignore),
+            "trust" => Ok(Self::Trust),
+            _ => Err(format!(
+                "Unknown service read-only mode: {}. Use 'ignore' or 'trust'",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ignore => "ignore",
+            Self::Trust => "trust",
+        }
+    }
+}
+
+/// Whether `start_run` of the agent service may loosen approval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServiceOverrides {
+    /// Reject runs that override the user-only settings or set `auto_approve`.
+    #[default]
+    Deny,
+    /// Let them, as the CLI flags can.
+    Allow,
+}
+
+impl ServiceOverrides {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "deny" => Ok(Self::Deny),
+            "allow" => Ok(Self::Allow),
+            _ => Err(format!(
+                "Unknown service override mode: {}. Use 'deny' or 'allow'",
+                value
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deny => "deny",
+            Self::Allow => "allow",
+        }
+    }
+}
+
+/// Effective agent settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub provider: ProviderKind,
+    pub model: String,
+    pub max_iterations: u64,
+    pub max_tokens: u64,
+    /// Spending cap per run in USD; `None` means no cap.
+    pub max_cost: Option<f64>,
+    /// Timeout of each tool call.
+    pub timeout_ms: u64,
+    /// Timeout of each model call; for streamed calls, of each read.
+    pub model_timeout_ms: u64,
+    /// Wall-clock limit for a whole run; `None` means no limit.
+    pub run_timeout_ms: Option<u64>,
+    /// Characters of a tool result sent to the model.
+    pub tool_output_limit: u64,
+    /// Estimated context size in tokens that triggers compaction; below
+    /// `max_tokens`, or the budget runs out first.
+    pub compact_threshold: u64,
+    pub compact_strategy: CompactStrategy,
+    /// Provider endpoint; `None` uses the provider's public API.
+    pub base_url: Option<String>,
+    /// Script of assistant turns for the `mock` provider.
+    pub mock_script: Option<String>,
+    pub approval_policy: ApprovalPolicy,
+    pub service_read_only: ServiceReadOnly,
+    pub service_overrides: ServiceOverrides,
+    /// Root directory file tools are confined to; `None` uses the current directory.
+    pub workspace: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            provider: ProviderKind::Anthropic,
+            model: "claude-sonnet-4-20250514".to_string(),
+            max_iterations: 50,
+            max_tokens: 100_000,
+            max_cost: None,
+            timeout_ms: 120_000,
+            model_timeout_ms: 600_000,
+            run_timeout_ms: None,
+            tool_output_limit: 30_000,
+            compact_threshold: 50_000,
+            compact_strategy: CompactStrategy::Summarize,
+            base_url: None,
+            mock_script: None,
+            approval_policy: ApprovalPolicy::Ask,
+            service_read_only: ServiceReadOnly::Ignore,
+            service_overrides: ServiceOverrides::Deny,
+            workspace: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Key/value pairs in display order.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        schema::SETTINGS
+            .iter()
+            .map(|spec| (spec.key, self.display(spec.key)))
+            .collect()
+    }
+
+    fn display(&self, key: &str) -> String {
+        match key {
+            "provider" => self.provider.to_string(),
+            "model" => self.model.clone(),
+            "max_iterations" => self.max_iterations.to_string(),
+            "max_tokens" => self.max_tokens.to_string(),
+            "max_cost" => self
+                .max_cost
+                .map(|cost| cost.to_string())
+                .unwrap_or_else(|| "(no cap)".to_string()),
+            "timeout_ms" => self.timeout_ms.to_string(),
+            "model_timeout_ms" => self.model_timeout_ms.to_string(),
+            "run_timeout_ms" => self
+                .run_timeout_ms
+                .map(|ms| ms.to_string())
+                .unwrap_or_else(|| "(no limit)".to_string()),
+            "tool_output_limit" => self.tool_output_limit.to_string(),
+            "compact_threshold" => self.compact_threshold.to_string(),
+            "compact_strategy" => self.compact_strategy.as_str().to_string(),
+            "base_url" => self
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "(provider default)".to_string()),
+            "mock_script" => self
+                .mock_script
+                .clone()
+                .unwrap_or_else(|| "(none)".to_string()),
+            "approval_policy" => self.approval_policy.as_str().to_string(),
+            "service_read_only" => self.service_read_only.as_str().to_string(),
+            "service_overrides" => self.service_overrides.as_str().to_string(),
+            "workspace" => self
+                .workspace
+                .clone()
+                .unwrap_or_else(|| "(current directory)".to_string()),
+            _ => String::new(),
+        }
+    }
+
+    /// Typed value of `key` for `--output json`; `null` when unset.
+    pub fn json_value(&self, key: &str) -> serde_json::Value {
+        self.toml_value(key)
+            .and_then(|value| serde_json::to_value(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Typed TOML value of `key`, as written to the config file.
+    fn toml_value(&self, key: &str) -> Option<toml::Value> {
+        let value = match key {
+            "provider" => toml::Value::String(self.provider.to_string()),
+            "model" => toml::Value::String(self.model.clone()),
+            "max_iterations" => toml::Value::Integer(self.max_iterations as i64),
+            "max_tokens" => toml::Value::Integer(self.max_tokens as i64),
+            "max_cost" => toml::Value::Float(self.max_cost?),
+            "timeout_ms" => toml::Value::Integer(self.timeout_ms as i64),
+            "model_timeout_ms" => toml::Value::Integer(self.model_timeout_ms as i64),
+            "run_timeout_ms" => toml::Value::Integer(self.run_timeout_ms? as i64),
+            "tool_output_limit" => toml::Value::Integer(self.tool_output_limit as i64),
+            "compact_threshold" => toml::Value::Integer(self.compact_threshold as i64),
+            "compact_strategy" => toml::Value::String(self.compact_strategy.as_str().to_string()),
+            "base_url" => toml::Value::String(self.base_url.clone()?),
+            "mock_script" => toml::Value::String(self.mock_script.clone()?),
+            "approval_policy" => toml::Value::String(self.approval_policy.as_str().to_string()),
+            "service_read_only" => toml::Value::String(self.service_read_only.as_str().to_string()),
+            "service_overrides" => toml::Value::String(self.service_overrides.as_str().to_string()),
+            "workspace" => toml::Value::String(self.workspace.clone()?),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Validate and apply a `config set` assignment.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        schema::lookup(key)?.check(value)?;
+        match key {
+            "provider" => self.provider = ProviderKind::parse(value)?,
+            "model" => self.model = value.to_string(),
+            "max_iterations" => self.max_iterations = parse_number(key, value)?,
+            "max_tokens" => self.max_tokens = parse_number(key, value)?,
+            "max_cost" => self.max_cost = Some(parse_amount(key, value)?),
+            "timeout_ms" => self.timeout_ms = parse_number(key, value)?,
+            "model_timeout_ms" => self.model_timeout_ms = parse_number(key, value)?,
+            "run_timeout_ms" => self.run_timeout_ms = Some(parse_number(key, value)?),
+            "tool_output_limit" => self.tool_output_limit = parse_number(key, value)?,
+            "compact_threshold" => self.compact_threshold = parse_number(key, value)?,
+            "compact_strategy" => self.compact_strategy = CompactStrategy::parse(value)?,
+            "base_url" => self.base_url = Some(value.to_string()),
+            "mock_script" => self.mock_script = Some(value.to_string()),
+            "approval_policy" => self.approval_policy = ApprovalPolicy::parse(value)?,
+            "service_read_only" => self.service_read_only = ServiceReadOnly::parse(value)?,
+            "service_overrides" => self.service_overrides = ServiceOverrides::parse(value)?,
+            "workspace" => self.workspace = Some(value.to_string()),
+            _ => return Err(format!("Unknown config key: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Apply the known keys of a config table, returning the keys that were set.
+    ///
+    /// Other entries (such as `[[tools]]`) are left for their own loaders.
+    fn apply_table(&mut self, table: &toml::Table) -> Result<Vec<&'static str>, String> {
+        let mut applied = Vec::new();
+        for spec in schema::SETTINGS {
+            let Some(value) = table.get(spec.key) else {
+                continue;
+            };
+            if let Some(text) = spec.check_toml(value)? {
+                self.set(spec.key, &text)?;
+                applied.push(spec.key);
+            }
+        }
+        Ok(applied)
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "Invalid value for {}: expected a number, got '{}'",
+            key, value
+        )
+    })
+}
+
+fn parse_amount(key: &str, value: &str) -> Result<f64, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "Invalid value for {}: expected an amount, got '{}'",
+            key, value
+        )
+    })
+}
+
+/// Milliseconds in a duration such as `90s`, `30m`, `2h` or `500ms`.
+///
+/// A bare number is taken as seconds.
+pub fn parse_duration_ms(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "ms" => 1,
+        "" | "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(n) if scale > 0 => Ok(n.saturating_mul(scale)),
+        _ => Err(format!(
+            "expected a duration such as 90s, 30m or 2h, got '{}'",
+            value
+        )),
+    }
+}
+
+// === Config Files ===
+
+/// Environment variable prefix for setting overrides, e.g. `ADI_AGENT_MODEL`.
+const ENV_PREFIX: &str = "ADI_AGENT_";
+
+/// Project config path, relative to the project root.
+const PROJECT_CONFIG: &str = ".adi/agent.toml";
+
+/// Config layer that supplied an effective setting.
+///
+/// Layers are applied in declaration order; later layers win.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    User(PathBuf),
+    Project(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl Origin {
+    /// Layer name together with the file, variable or flag that set the value.
+    pub fn detail(&self) -> String {
+        match self {
+            Origin::Default => self.to_string(),
+            Origin::User(path) | Origin::Project(path) => {
+                format!("{}: {}", self, path.display())
+            }
+            Origin::Env(name) | Origin::Flag(name) => format!("{}: {}", self, name),
+        }
+    }
+
+    /// The file, variable or flag that set the value, if any.
+    pub fn source(&self) -> Option<String> {
+        match self {
+            Origin::Default => None,
+            Origin::User(path) | Origin::Project(path) => Some(path.display().to_string()),
+            Origin::Env(name) | Origin::Flag(name) => Some(name.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Origin::Default => "default",
+            Origin::User(_) => "user config",
+            Origin::Project(_) => "project config",
+            Origin::Env(_) => "env",
+            Origin::Flag(_) => "flag",
+        })
+    }
+}
+
+/// How a command tool receives its input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolInput {
+    /// The JSON input is written to stdin.
+    #[default]
+    Stdin,
+    /// Input values are only passed through `{param}` placeholders in `args`.
+    Argv,
+}
+
+/// A `[[tools]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolConfig {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub input: ToolInput,
+    #[serde(default)]
+    pub parameters: Option<toml::Table>,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// What a `[[policy]]` rule does with a matching tool call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Allow,
+    Ask,
+    Deny,
+}
+
+impl PolicyAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Ask => "ask",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+/// A `[[policy]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PolicyRule {
+    /// Tool name or glob, e.g. `shell` or `*`.
+    pub tool: String,
+    pub action: PolicyAction,
+    /// Glob per argument; a leading `!` negates it.
+    #[serde(default)]
+    pub args: BTreeMap<String, String>,
+    /// Regex per argument.
+    #[serde(default)]
+    pub regex: BTreeMap<String, String>,
+    /// Explanation returned to the model when the rule denies a call.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// File and index the rule was read from.
+    #[serde(skip)]
+    pub location: String,
+}
+
+/// A `[[prices]]` entry, in USD per million tokens.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PriceConfig {
+    /// Model name or glob, e.g. `claude-sonnet-4*`.
+    pub model: String,
+    pub input: f64,
+    pub output: f64,
+    /// Defaults to the input price.
+    #[serde(default)]
+    pub cache_read: Option<f64>,
+    /// Defaults to the input price.
+    #[serde(default)]
+    pub cache_write: Option<f64>,
+    /// File and index the entry was read from.
+    #[serde(skip)]
+    pub location: String,
+}
+
+/// Effective settings together with the origin of each key.
+///
+/// Resolution order: defaults, user file, project file, `ADI_AGENT_*`
+/// environment variables, then per-invocation flags. The keys in
+/// `schema::USER_ONLY` come from the user file and flags only.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub settings: Settings,
+    pub user_path: PathBuf,
+    pub project_path: Option<PathBuf>,
+    /// `[[tools]]` entries of the user file.
+    pub tools: Vec<ToolConfig>,
+    /// `[[policy]]` rules of the user file, in evaluation order.
+    pub policy: Vec<PolicyRule>,
+    /// `[[prices]]` entries in lookup order; project entries come first.
+    pub prices: Vec<PriceConfig>,
+    origins: BTreeMap<&'static str, Origin>,
+}
+
+impl Config {
+    /// Resolve every layer except per-invocation flags.
+    pub fn load() -> Result<Self, String> {
+        Self::resolve(&[])
+    }
+
+    /// Resolve every layer; `flags` are `(key, value)` overrides from the command line.
+    pub fn resolve(flags: &[(&str, &str)]) -> Result<Self, String> {
+        Self::resolve_files(user_config_path()?, find_project_config(), flags)
+    }
+
+    fn resolve_files(
+        user_path: PathBuf,
+        project_path: Option<PathBuf>,
+        flags: &[(&str, &str)],
+    ) -> Result<Self, String> {
+        let mut config = Self {
+            settings: Settings::default(),
+            user_path: user_path.clone(),
+            project_path: project_path.clone(),
+            tools: Vec::new(),
+            policy: Vec::new(),
+            prices: Vec::new(),
+            origins: BTreeMap::new(),
+        };
+
+        config.apply_file(&user_path, Origin::User(user_path.clone()))?;
+        if let Some(path) = project_path {
+            config.apply_file(&path, Origin::Project(path.clone()))?;
+        }
+
+        for spec in schema::SETTINGS {
+            let name = env_var_name(spec.key);
+            if let Ok(value) = std::env::var(&name) {
+                if schema::USER_ONLY.contains(&spec.key) {
+                    return Err(format!(
+                        "{}: {} can only be set in {} or with --{}",
+                        name,
+                        spec.key,
+                        config.user_path.display(),
+                        spec.key.replace('_', "-")
+                    ));
+                }
+                config
+                    .settings
+                    .set(spec.key, &value)
+                    .map_err(|e| format!("{}: {}", name, e))?;
+                config.origins.insert(spec.key, Origin::Env(name));
+            }
+        }
+
+        for (key, value) in flags {
+            let flag = format!("--{}", key.replace('_', "-"));
+            let spec = schema::lookup(key)?;
+            config
+                .settings
+                .set(spec.key, value)
+                .map_err(|e| format!("{}: {}", flag, e))?;
+            config.origins.insert(spec.key, Origin::Flag(flag));
+        }
+
+        let settings = &config.settings;
+        if settings.compact_strategy != CompactStrategy::Off
+            && settings.compact_threshold >= settings.max_tokens
+        {
+            return Err(format!(
+                "compact_threshold ({}) must be below max_tokens ({}), or the token budget \
+                 runs out before the context is compacted; lower it or set compact_strategy = \"off\"",
+                settings.compact_threshold, settings.max_tokens
+            ));
+        }
+
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: &Path, origin: Origin) -> Result<(), String> {
+        let table = ConfigFile::load(path)?.table()?;
+        if matches!(origin, Origin::Project(_)) {
+            if let Some(key) = schema::USER_ONLY
+                .iter()
+                .find(|key| table.contains_key(**key))
+            {
+                return Err(format!(
+                    "{}: {} can only be set in the user config {}",
+                    path.display(),
+                    key,
+                    self.user_path.display()
+                ));
+            }
+        }
+        let applied = self
+            .settings
+            .apply_table(&table)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for key in applied {
+            self.origins.insert(key, origin.clone());
+        }
+
+        if let Some(tools) = table.get("tools") {
+            let tools: Vec<ToolConfig> = tools
+                .clone()
+                .try_into()
+                .map_err(|e| format!("{}: invalid [[tools]]: {}", path.display(), e))?;
+            for tool in tools {
+                self.tools.retain(|existing| existing.name != tool.name);
+                self.tools.push(tool);
+            }
+        }
+
+        if let Some(policy) = table.get("policy") {
+            let mut rules: Vec<PolicyRule> = policy
+                .clone()
+                .try_into()
+                .map_err(|e| format!("{}: invalid [[policy]]: {}", path.display(), e))?;
+            for (index, rule) in rules.iter_mut().enumerate() {
+                rule.location = format!("{} policy[{}]", path.display(), index);
+            }
+            self.policy.extend(rules);
+        }
+
+        if let Some(prices) = table.get("prices") {
+            let mut prices: Vec<PriceConfig> = prices
+                .clone()
+                .try_into()
+                .map_err(|e| format!("{}: invalid [[prices]]: {}", path.display(), e))?;
+            for (index, price) in prices.iter_mut().enumerate() {
+                price.location = format!("{} prices[{}]", path.display(), index);
+            }
+            self.prices.splice(0..0, prices);
+        }
+        Ok(())
+    }
+
+    pub fn origin(&self, key: &str) -> &Origin {
+        self.origins.get(key).unwrap_or(&Origin::Default)
+    }
+}
+
+/// Environment variable overriding `key`, e.g. `ADI_AGENT_MAX_ITERATIONS`.
+pub fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_ascii_uppercase())
+}
+
+/// Nearest `.adi/agent.toml` in the current directory or its ancestors.
+pub fn find_project_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// Project config path to write to: the nearest existing one, or `./.adi/agent.toml`.
+pub fn project_config_path() -> Result<PathBuf, String> {
+    match find_project_config() {
+        Some(path) => Ok(path),
+        None => std::env::current_dir()
+            .map(|cwd| cwd.join(PROJECT_CONFIG))
+            .map_err(|e| format!("Cannot determine current directory: {}", e)),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/adi/agent.toml`, falling back to `~/.config/adi/agent.toml`.
+pub fn user_config_path() -> Result<PathBuf, String> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("HOME")
+            .filter(|v| !v.is_empty())
+            .map(|home| PathBuf::from(home).join(".config"))
+            .ok_or("Cannot locate config directory: neither XDG_CONFIG_HOME nor HOME is set")?,
+    };
+    Ok(base.join("adi").join("agent.toml"))
+}
+
+/// A TOML config file loaded for editing.
+///
+/// Comments, key order and formatting of untouched entries are kept.
+pub struct ConfigFile {
+    path: PathBuf,
+    doc: toml_edit::DocumentMut,
+}
+
+impl ConfigFile {
+    /// Load `path`; a missing file is treated as empty.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let doc = match fs::read_to_string(path) {
+            Ok(content) => content
+                .parse::<toml_edit::DocumentMut>()
+                .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => toml_edit::DocumentMut::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            doc,
+        })
+    }
+
+    /// Validate and store `key = value`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut settings = Settings::default();
+        settings.set(key, value)?;
+        let Some(typed) = settings.toml_value(key) else {
+            return Ok(());
+        };
+        let mut value = match typed {
+            toml::Value::String(s) => toml_edit::Value::from(s),
+            toml::Value::Integer(n) => toml_edit::Value::from(n),
+            toml::Value::Float(n) => toml_edit::Value::from(n),
+            toml::Value::Boolean(b) => toml_edit::Value::from(b),
+            other => return Err(format!("Cannot store {} in a config file", other)),
+        };
+        match self.doc.get_mut(key).and_then(|item| item.as_value_mut()) {
+            // Keep a trailing comment on the line.
+            Some(existing) => {
+                *value.decor_mut() = existing.decor().clone();
+                *existing = value;
+            }
+            None => {
+                self.doc.insert(key, toml_edit::Item::Value(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove `key`, so the next layer or the default applies again.
+    ///
+    /// Returns whether the file set it.
+    pub fn unset(&mut self, key: &str) -> Result<bool, String> {
+        schema::lookup(key)?;
+        Ok(self.doc.remove(key).is_some())
+    }
+
+    /// The file as plain TOML values.
+    fn table(&self) -> Result<toml::Table, String> {
+        self.doc
+            .to_string()
+            .parse()
+            .map_err(|e| format!("Invalid config {}: {}", self.path.display(), e))
+    }
+
+    /// Write the file atomically.
+    pub fn save(&self) -> Result<(), String> {
+        write_atomic(&self.path, self.doc.to_string().as_bytes())
+    }
+}
+
+/// Validate a config file, returning `path:line: message` lines for each problem.
+pub fn validate_file(path: &Path) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(schema::validate(&content)
+        .into_iter()
+        .map(|d| match d.line {
+            Some(line) => format!("{}:{}: {}", path.display(), line, d.message),
+            None => format!("{}: {}", path.display(), d.message),
+        })
+        .collect())
+}
+
+/// Write `content` to a temporary file next to `path`, then rename it over `path`.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<(), String> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| format!("Invalid path: {}", path.display()))?;
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(".{}.tmp-{}", file_name, std::process::id()));
+    let result = fs::File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(format!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_unset_keep_comments_and_order() {
+        let dir = std::env::temp_dir().join(format!("adi-agent-config-{}", std::process::id()));
+        let path = dir.join("agent.toml");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &path,
+            "# agent\nmodel = \"a\" # favourite\nmax_cost = 1.5\n\n[[tools]]\nname = \"t\"\ncommand = \"echo\"\n",
+        )
+        .unwrap();
+
+        let mut file = ConfigFile::load(&path).unwrap();
+        file.set("model", "b").unwrap();
+        file.set("max_iterations", "5").unwrap();
+        assert!(file.unset("max_cost").unwrap());
+        assert!(!file.unset("run_timeout_ms").unwrap());
+        assert!(file.unset("bogus").is_err());
+        file.save().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            content,
+            "# agent\nmodel = \"b\" # favourite\nmax_iterations = 5\n\n[[tools]]\nname = \"t\"\ncommand = \"echo\"\n"
+        );
+    }
+
+    #[test]
+    fn project_file_cannot_loosen_the_user_config() {
+        let dir = std::env::temp_dir().join(format!("adi-agent-layers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let project = dir.join("project.toml");
+        fs::write(
+            &user,
+            "model = \"user\"\napproval_policy = \"ask\"\n\n[[policy]]\ntool = \"shell\"\naction = \"deny\"\n",
+        )
+        .unwrap();
+        let resolve = |project_content: &str, flags: &[(&str, &str)]| {
+            fs::write(&project, project_content).unwrap();
+            Config::resolve_files(user.clone(), Some(project.clone()), flags)
+        };
+
+        let config = resolve("model = \"project\"\nmax_iterations = 7\n", &[]).unwrap();
+        assert_eq!(config.settings.model, "project");
+        assert_eq!(config.settings.max_iterations, 7);
+        assert_eq!(config.settings.approval_policy, ApprovalPolicy::Ask);
+        assert_eq!(config.policy.len(), 1);
+        assert_eq!(config.policy[0].action, PolicyAction::Deny);
+
+        for loosening in [
+            "approval_policy = \"auto\"\n",
+            "service_read_only = \"trust\"\n",
+            "workspace = \"/\"\n",
+            "[[policy]]\ntool = \"shell\"\naction = \"allow\"\n",
+            "[[tools]]\nname = \"shell\"\ncommand = \"sh\"\n",
+        ] {
+            let err = resolve(loosening, &[]).unwrap_err();
+            assert!(
+                err.contains("can only be set in the user config"),
+                "{}",
+                err
+            );
+        }
+
+        let config = resolve("", &[("approval_policy", "auto")]).unwrap();
+        assert_eq!(config.settings.approval_policy, ApprovalPolicy::Auto);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_must_start_before_the_budget_runs_out() {
+        let user =
+            std::env::temp_dir().join(format!("adi-agent-missing-{}.toml", std::process::id()));
+        let defaults = Settings::default();
+        assert!(defaults.compact_threshold < defaults.max_tokens);
+
+        let err = Config::resolve_files(user.clone(), None, &[("compact_threshold", "100000")])
+            .unwrap_err();
+        assert!(err.contains("must be below max_tokens (100000)"), "{}", err);
+
+        let flags = [("compact_threshold", "100000"), ("compact_strategy", "off")];
+        assert!(Config::resolve_files(user, None, &flags).is_ok());
+    }
+}
@@ -0,0 +1,603 @@
+//! Typed schema for `agent.toml`.
+
+use crate::suggest;
+
+/// Value type and constraints of a config key.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyType {
+    Text,
+    /// `http://` or `https://` URL.
+    Url,
+    Integer {
+        min: u64,
+        max: u64,
+    },
+    /// Non-negative decimal, e.g. a price in USD.
+    Amount,
+    Choice(&'static [&'static str]),
+    StringList,
+    /// Table of string values, e.g. environment variables.
+    StringMap,
+    /// Free-form table, e.g. a JSON schema.
+    Table,
+}
+
+/// Schema entry for one key.
+#[derive(Debug, Clone, Copy)]
+pub struct KeySpec {
+    pub key: &'static str,
+    pub ty: KeyType,
+    pub required: bool,
+    pub description: &'static str,
+}
+
+const fn key(key: &'static str, ty: KeyType, description: &'static str) -> KeySpec {
+    KeySpec {
+        key,
+        ty,
+        required: false,
+        description,
+    }
+}
+
+const fn required(key: &'static str, ty: KeyType, description: &'static str) -> KeySpec {
+    KeySpec {
+        key,
+        ty,
+        required: true,
+        description,
+    }
+}
+
+/// Keys that decide what the agent may do without asking. A cloned
+/// repository must not loosen them, so only the user config and
+/// command-line flags set them, never a project file or the environment.
+/// `start_run` of the agent service may only override them if the user
+/// config sets `service_overrides = "allow"`.
+pub const USER_ONLY: &[&str] = &[
+    "approval_policy",
+    "service_read_only",
+    "service_overrides",
+    "workspace",
+    "tools",
+    "policy",
+];
+
+/// Top-level settings, in display order.
+pub const SETTINGS: &[KeySpec] = &[
+    key(
+        "provider",
+        KeyType::Choice(&["anthropic", "openai-compatible", "mock"]),
+        "LLM backend",
+    ),
+    key("model", KeyType::Text, "Model name sent to the provider"),
+    key(
+        "max_iterations",
+        KeyType::Integer { min: 1, max: 1000 },
+        "Maximum model calls per run",
+    ),
+    key(
+        "max_tokens",
+        KeyType::Integer {
+            min: 1_000,
+            max: 10_000_000,
+        },
+        "Token budget per run, input plus output",
+    ),
+    key(
+        "timeout_ms",
+        KeyType::Integer {
+            min: 1_000,
+            max: 3_600_000,
+        },
+        "Timeout for each tool call, in milliseconds",
+    ),
+    key(
+        "model_timeout_ms",
+        KeyType::Integer {
+            min: 1_000,
+            max: 3_600_000,
+        },
+        "Timeout for each model call, in milliseconds; a streamed call only times out while no data arrives",
+    ),
+    key(
+        "run_timeout_ms",
+        KeyType::Integer {
+            min: 1_000,
+            max: 86_400_000,
+        },
+        "Wall-clock limit for a whole run, in milliseconds",
+    ),
+    key(
+        "tool_output_limit",
+        KeyType::Integer {
+            min: 1_000,
+            max: 10_000_000,
+        },
+        "Characters of a tool result sent to the model; longer results are saved to a file",
+    ),
+    key(
+        "compact_threshold",
+        KeyType::Integer {
+            min: 1_000,
+            max: 10_000_000,
+        },
+        "Context size in tokens that triggers compaction; must be below max_tokens",
+    ),
+    key(
+        "compact_strategy",
+        KeyType::Choice(&["summarize", "truncate", "off"]),
+        "How older turns are compacted",
+    ),
+    key("max_cost", KeyType::Amount, "Spending cap per run, in USD"),
+    key("base_url", KeyType::Url, "Provider endpoint"),
+    key(
+        "mock_script",
+        KeyType::Text,
+        "Script of assistant turns for the mock provider",
+    ),
+    key(
+        "approval_policy",
+        KeyType::Choice(&["ask", "auto", "deny"]),
+        "How side-effecting tool calls are approved",
+    ),
+    key(
+        "service_read_only",
+        KeyType::Choice(&["ignore", "trust"]),
+        "Whether tool-provider services may mark tools read-only, so their calls skip approval",
+    ),
+    key(
+        "service_overrides",
+        KeyType::Choice(&["deny", "allow"]),
+        "Whether start_run of the agent service may set auto_approve or the other user-only keys",
+    ),
+    key(
+        "workspace",
+        KeyType::Text,
+        "Root directory file tools are confined to",
+    ),
+];
+
+/// Fields of a `[[tools]]` entry.
+pub const TOOL_FIELDS: &[KeySpec] = &[
+    required("name", KeyType::Text, "Tool name offered to the model"),
+    required("command", KeyType::Text, "Program to run"),
+    key(
+        "description",
+        KeyType::Text,
+        "Description offered to the model",
+    ),
+    key(
+        "args",
+        KeyType::StringList,
+        "Arguments; `{param}` is replaced by the input value",
+    ),
+    key(
+        "input",
+        KeyType::Choice(&["stdin", "argv"]),
+        "How the JSON input is passed",
+    ),
+    key("parameters", KeyType::Table, "JSON schema of the input"),
+    key("cwd", KeyType::Text, "Working directory"),
+    key("env", KeyType::StringMap, "Extra environment variables"),
+    key(
+        "timeout_ms",
+        KeyType::Integer {
+            min: 1_000,
+            max: 3_600_000,
+        },
+        "Timeout in milliseconds",
+    ),
+];
+
+/// Fields of a `[[policy]]` entry.
+pub const POLICY_FIELDS: &[KeySpec] = &[
+    required(
+        "tool",
+        KeyType::Text,
+        "Tool name or glob the rule applies to",
+    ),
+    required(
+        "action",
+        KeyType::Choice(&["allow", "ask", "deny"]),
+        "What to do with a matching call",
+    ),
+    key(
+        "args",
+        KeyType::StringMap,
+        "Glob per argument; a leading `!` negates it",
+    ),
+    key("regex", KeyType::StringMap, "Regex per argument"),
+    key(
+        "reason",
+        KeyType::Text,
+        "Explanation sent to the model on deny",
+    ),
+];
+
+/// Fields of a `[[prices]]` entry; prices are in USD per million tokens.
+pub const PRICE_FIELDS: &[KeySpec] = &[
+    required(
+        "model",
+        KeyType::Text,
+        "Model name or glob the price applies to",
+    ),
+    required("input", KeyType::Amount, "Price of input tokens"),
+    required("output", KeyType::Amount, "Price of output tokens"),
+    key(
+        "cache_read",
+        KeyType::Amount,
+        "Price of tokens read from the prompt cache",
+    ),
+    key(
+        "cache_write",
+        KeyType::Amount,
+        "Price of tokens written to the prompt cache",
+    ),
+];
+
+/// Schema entry of a top-level setting, with a suggestion for unknown keys.
+pub fn lookup(key: &str) -> Result<&'static KeySpec, String> {
+    if key == "tools" || key == "policy" || key == "prices" {
+        return Err(format!(
+            "{} is configured as [[{}]] tables in agent.toml",
+            key, key
+        ));
+    }
+    SETTINGS.iter().find(|spec| spec.key == key).ok_or_else(|| {
+        suggest::with_suggestion(
+            format!("Unknown config key: {}", key),
+            key,
+            SETTINGS.iter().map(|spec| spec.key),
+        )
+    })
+}
+
+impl KeySpec {
+    /// Check a value given on the command line or in an environment variable.
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        match self.ty {
+            KeyType::Text => Ok(()),
+            KeyType::Url => {
+                if value.starts_with("http://") || value.starts_with("https://") {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Invalid value for {}: expected an http:// or https:// URL, got '{}'",
+                        self.key, value
+                    ))
+                }
+            }
+            KeyType::Integer { min, max } => match value.parse::<u64>() {
+                Ok(n) if (min..=max).contains(&n) => Ok(()),
+                _ => Err(format!(
+                    "Invalid value for {}: expected a number between {} and {}, got '{}'",
+                    self.key, min, max, value
+                )),
+            },
+            KeyType::Amount => match value.parse::<f64>() {
+                Ok(n) if n.is_finite() && n >= 0.0 => Ok(()),
+                _ => Err(format!(
+                    "Invalid value for {}: expected a non-negative amount, got '{}'",
+                    self.key, value
+                )),
+            },
+            KeyType::Choice(choices) => {
+                if choices.contains(&value) {
+                    Ok(())
+                } else {
+                    Err(suggest::with_suggestion(
+                        format!(
+                            "Invalid value for {}: expected one of {}, got '{}'",
+                            self.key,
+                            choices.join(", "),
+                            value
+                        ),
+                        value,
+                        choices.iter().copied(),
+                    ))
+                }
+            }
+            KeyType::StringList | KeyType::StringMap | KeyType::Table => Err(format!(
+                "{} cannot be set from the command line; edit agent.toml",
+                self.key
+            )),
+        }
+    }
+
+    /// Check a value read from a config file.
+    ///
+    /// Scalars are returned in their command-line form.
+    pub fn check_toml(&self, value: &toml::Value) -> Result<Option<String>, String> {
+        let mismatch = |expected: &str| {
+            Err(format!(
+                "Invalid value for {}: expected {}, got {}",
+                self.key,
+                expected,
+                value.type_str()
+            ))
+        };
+        match (self.ty, value) {
+            (KeyType::Integer { .. }, toml::Value::Integer(n)) => {
+                let text = n.to_string();
+                self.check(&text)?;
+                Ok(Some(text))
+            }
+            (KeyType::Integer { .. }, _) => mismatch("an integer"),
+            (KeyType::Amount, toml::Value::Integer(_) | toml::Value::Float(_)) => {
+                let text = value.to_string();
+                self.check(&text)?;
+                Ok(Some(text))
+            }
+            (KeyType::Amount, _) => mismatch("a number"),
+            (KeyType::Text | KeyType::Url | KeyType::Choice(_), toml::Value::String(s)) => {
+                self.check(s)?;
+                Ok(Some(s.clone()))
+            }
+            (KeyType::Text | KeyType::Url | KeyType::Choice(_), _) => mismatch("a string"),
+            (KeyType::StringList, toml::Value::Array(items)) => {
+                if items.iter().all(|item| item.is_str()) {
+                    Ok(None)
+                } else {
+                    mismatch("an array of strings")
+                }
+            }
+            (KeyType::StringList, _) => mismatch("an array of strings"),
+            (KeyType::StringMap, toml::Value::Table(table)) => {
+                if table.values().all(|item| item.is_str()) {
+                    Ok(None)
+                } else {
+                    mismatch("a table of strings")
+                }
+            }
+            (KeyType::StringMap, _) => mismatch("a table of strings"),
+            (KeyType::Table, toml::Value::Table(_)) => Ok(None),
+            (KeyType::Table, _) => mismatch("a table"),
+        }
+    }
+}
+
+// === Validation ===
+
+/// A problem found in a config file.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Check a whole config file against the schema.
+pub fn validate(content: &str) -> Vec<Diagnostic> {
+    let table = match content.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(e) => {
+            return vec![Diagnostic {
+                line: e.span().map(|span| line_at(content, span.start)),
+                message: e.message().to_string(),
+            }]
+        }
+    };
+    // Parsed a second time with spans so problems can be located.
+    let Ok(doc) = toml_edit::ImDocument::parse(content) else {
+        return Vec::new();
+    };
+    let root = doc.as_table();
+    let key_line = |key: &str| {
+        root.key(key)
+            .and_then(|k| k.span())
+            .map(|span| line_at(content, span.start))
+    };
+
+    let mut diagnostics = Vec::new();
+    for (key, value) in &table {
+        if key == "tools" {
+            diagnostics.extend(validate_tables(content, root, key, value, TOOL_FIELDS));
+            continue;
+        }
+        if key == "policy" {
+            diagnostics.extend(validate_tables(content, root, key, value, POLICY_FIELDS));
+            continue;
+        }
+        if key == "prices" {
+            diagnostics.extend(validate_tables(content, root, key, value, PRICE_FIELDS));
+            continue;
+        }
+        let result = lookup(key).and_then(|spec| spec.check_toml(value).map(|_| ()));
+        if let Err(message) = result {
+            diagnostics.push(Diagnostic {
+                line: key_line(key),
+                message,
+            });
+        }
+    }
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+/// Check an array of tables such as `[[tools]]` against `fields`.
+fn validate_tables(
+    content: &str,
+    root: &toml_edit::Table,
+    section: &str,
+    value: &toml::Value,
+    fields: &[KeySpec],
+) -> Vec<Diagnostic> {
+    let tables = root.get(section).and_then(|item| item.as_array_of_tables());
+    let Some(entries) = value.as_array() else {
+        return vec![Diagnostic {
+            line: root
+                .key(section)
+                .and_then(|k| k.span())
+                .map(|span| line_at(content, span.start)),
+            message: format!("{} must be declared as [[{}]] tables", section, section),
+        }];
+    };
+
+    let mut diagnostics = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let table = tables.and_then(|t| t.get(index));
+        let entry_line = table
+            .and_then(|t| t.span())
+            .map(|span| line_at(content, span.start));
+        let field_line = |field: &str| {
+            table
+                .and_then(|t| t.key(field))
+                .and_then(|k| k.span())
+                .map(|span| line_at(content, span.start))
+                .or(entry_line)
+        };
+
+        let Some(entry) = entry.as_table() else {
+            diagnostics.push(Diagnostic {
+                line: entry_line,
+                message: format!("{}[{}] must be a table", section, index),
+            });
+            continue;
+        };
+        let label = |field: &str, kind: &str| {
+            let value = entry.get(field).and_then(|v| v.as_str())?;
+            Some(format!("{} '{}'", kind, value))
+        };
+        let name = match section {
+            "tools" => label("name", "tool"),
+            "prices" => label("model", "price"),
+            _ => None,
+        }
+        .unwrap_or_else(|| format!("{}[{}]", section, index));
+
+        for spec in fields {
+            if spec.required && !entry.contains_key(spec.key) {
+                diagnostics.push(Diagnostic {
+                    line: entry_line,
+                    message: format!("{}: missing required field '{}'", name, spec.key),
+                });
+            }
+        }
+        for (field, value) in entry {
+            let result = match fields.iter().find(|spec| spec.key == field) {
+                Some(spec) => spec.check_toml(value).map(|_| ()),
+                None => Err(suggest::with_suggestion(
+                    format!("Unknown field: {}", field),
+                    field,
+                    fields.iter().map(|spec| spec.key),
+                )),
+            };
+            if let Err(message) = result {
+                diagnostics.push(Diagnostic {
+                    line: field_line(field),
+                    message: format!("{}: {}", name, message),
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+/// 1-based line number of byte `offset` in `content`.
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(content: &str) -> Vec<(Option<usize>, String)> {
+        validate(content)
+            .into_iter()
+            .map(|d| (d.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn valid_file_has_no_problems() {
+        let content = r#"
+model = "claude-sonnet-4-20250514"
+max_iterations = 10
+max_cost = 2
+approval_policy = "auto"
+
+[[tools]]
+name = "grep"
+command = "rg"
+args = ["{pattern}"]
+
+[[policy]]
+tool = "shell"
+action = "deny"
+"#;
+        assert!(problems(content).is_empty());
+    }
+
+    #[test]
+    fn unknown_key_is_reported_on_its_line_with_a_suggestion() {
+        let problems = problems("model = \"x\"\nmax_iteratons = 5\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, Some(2));
+        assert_eq!(
+            problems[0].1,
+            "Unknown config key: max_iteratons (did you mean 'max_iterations'?)"
+        );
+    }
+
+    #[test]
+    fn type_and_range_errors() {
+        let problems =
+            problems("max_iterations = \"ten\"\n\nmax_tokens = 5\nprovider = \"antropic\"\n");
+        assert_eq!(problems.len(), 3);
+        assert_eq!(
+            problems[0],
+            (
+                Some(1),
+                "Invalid value for max_iterations: expected an integer, got string".to_string()
+            )
+        );
+        assert_eq!(problems[1].0, Some(3));
+        assert!(problems[1].1.contains("between 1000 and 10000000"));
+        assert_eq!(problems[2].0, Some(4));
+        assert!(problems[2].1.ends_with("(did you mean 'anthropic'?)"));
+    }
+
+    #[test]
+    fn table_entries_are_checked_field_by_field() {
+        let content =
+            "[[tools]]\nname = \"grep\"\ncomand = \"rg\"\n\n[[tools]]\ncommand = \"ls\"\n";
+        let problems = problems(content);
+        assert_eq!(
+            problems,
+            vec![
+                (
+                    Some(1),
+                    "tool 'grep': missing required field 'command'".to_string()
+                ),
+                (
+                    Some(3),
+                    "tool 'grep': Unknown field: comand (did you mean 'command'?)".to_string()
+                ),
+                (
+                    Some(5),
+                    "tools[1]: missing required field 'name'".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_error_is_located() {
+        let problems = problems("model = \"x\"\nmax_iterations = \n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, Some(2));
+    }
+
+    #[test]
+    fn check_rejects_values_outside_the_schema() {
+        let spec = lookup("base_url").unwrap();
+        assert!(spec.check("https://example.com").is_ok());
+        assert!(spec.check("example.com").is_err());
+        assert!(lookup("max_cost").unwrap().check("-1").is_err());
+        assert_eq!(
+            lookup("tools").unwrap_err(),
+            "tools is configured as [[tools]] tables in agent.toml"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Context-window management for long runs.
+//!
+//! Once the conversation sent to the model passes `compact_threshold`
+//! estimated tokens, older turns are replaced by a summary while the
+//! original task and the most recent replies are kept. The saved transcript
+//! keeps every message; each compaction is recorded in the session and only
+//! changes what the model is sent.
+
+use crate::config::CompactStrategy;
+use crate::message::{ContentBlock, Message, Role, ToolDefinition, Usage};
+use crate::provider::{self, Provider};
+use crate::session::{self, Session};
+use serde::{Deserialize, Serialize};
+
+/// Model replies, with the messages after them, that are never compacted.
+const KEEP_RECENT_REPLIES: usize = 4;
+
+/// Characters of each block included in the text to summarize.
+const EXCERPT_LIMIT: usize = 2000;
+
+const SUMMARY_PROMPT: &str = "You summarize the earlier part of an agent's conversation so the \
+agent can continue its task with less context. Keep the task and any follow-up instructions, \
+decisions made, facts learned (file paths, commands, results, errors) and what remains to be \
+done. Reply with the summary only.";
+
+/// A compaction recorded in the session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Compaction {
+    /// Messages `1..end` are replaced by `summary` when talking to the model.
+    pub end: usize,
+    pub strategy: CompactStrategy,
+    pub summary: String,
+    /// Estimated tokens of the conversation before and after compacting.
+    pub tokens_before: u64,
+    pub tokens_after: u64,
+    /// Unix timestamp in seconds.
+    pub at: u64,
+    /// Usage of the summarizing call; zero for truncation.
+    #[serde(default)]
+    pub usage: Usage,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
+}
+
+/// Messages sent to the model for `session`.
+///
+/// After a compaction this is the first user message with the latest summary
+/// appended, followed by the messages from that compaction's `end`.
+pub fn view(session: &Session) -> Vec<Message> {
+    let Some(compaction) = session.compactions.last() else {
+        return session.messages.clone();
+    };
+    let mut first = session.messages[0].clone();
+    first.content.push(ContentBlock::Text {
+        text: summary_text(compaction),
+    });
+    let mut messages = vec![first];
+    messages.extend_from_slice(&session.messages[compaction.end..]);
+    messages
+}
+
+/// Estimated input tokens of a model call with `messages`.
+pub fn tokens(system: &str, messages: &[Message], tools: &[ToolDefinition]) -> u64 {
+    provider::estimate_usage(system, messages, tools, &[]).input_tokens
+}
+
+/// Compact the older part of `session`'s view.
+///
+/// Returns `None` when every reply is recent enough to be kept, or when
+/// `allow` refuses the summarizing call, which it is given the system prompt
+/// and messages of. The caller records the compaction and fills in
+/// `tokens_after` and `cost_usd`.
+pub fn compact(
+    provider: &mut dyn Provider,
+    session: &Session,
+    strategy: CompactStrategy,
+    tokens_before: u64,
+    allow: &mut dyn FnMut(&str, &[Message]) -> bool,
+) -> Result<Option<Compaction>, String> {
+    let start = session.compactions.last().map_or(1, |c| c.end);
+    let replies: Vec<usize> = (start..session.messages.len())
+        .filter(|&index| session.messages[index].role == Role::Assistant)
+        .collect();
+    if replies.len() <= KEEP_RECENT_REPLIES {
+        return Ok(None);
+    }
+    let end = replies[replies.len() - KEEP_RECENT_REPLIES];
+
+    let mut compaction = Compaction {
+        end,
+        strategy,
+        summary: String::new(),
+        tokens_before,
+        tokens_after: 0,
+        at: session::now(),
+        usage: Usage::default(),
+        model: String::new(),
+        cost_usd: None,
+    };
+    match strategy {
+        CompactStrategy::Summarize => {
+            let mut older = view(session);
+            older.truncate(1 + end - start);
+            let request = [Message::user(render(&older))];
+            if !allow(SUMMARY_PROMPT, &request) {
+                return Ok(None);
+            }
+            let response = provider.complete(SUMMARY_PROMPT, &request, &[])?;
+            let summary = response
+                .content
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            if summary.trim().is_empty() {
+                return Err("the model returned an empty summary".to_string());
+            }
+            compaction.usage = response.usage.unwrap_or_else(|| {
+                provider::estimate_usage(SUMMARY_PROMPT, &request, &[], &response.content)
+            });
+            compaction.model = session.model.clone();
+            compaction.summary = summary.trim().to_string();
+        }
+        CompactStrategy::Truncate => {
+            compaction.summary = format!("{} earlier messages were dropped.", end - 1);
+        }
+        CompactStrategy::Off => return Ok(None),
+    }
+    Ok(Some(compaction))
+}
+
+fn summary_text(compaction: &Compaction) -> String {
+    format!(
+        "[Earlier turns were compacted to fit the context window. Summary of the \
+         conversation so far:]\n\n{}",
+        compaction.summary
+    )
+}
+
+/// Plain-text rendering of `messages` for the summarizing call.
+fn render(messages: &[Message]) -> String {
+    let mut output = String::new();
+    for message in messages {
+        let role = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        for block in &message.content {
+            let (label, text) = match block {
+                ContentBlock::Text { text } => (role.to_string(), excerpt(text)),
+                ContentBlock::ToolUse { name, input, .. } => {
+                    (format!("tool call {}", name), excerpt(&input.to_string()))
+                }
+                ContentBlock::ToolResult {
+                    content, is_error, ..
+                } => {
+                    let label = if *is_error {
+                        "tool error"
+                    } else {
+                        "tool result"
+                    };
+                    (label.to_string(), excerpt(content))
+                }
+            };
+            output.push_str(&format!("[{}]\n{}\n\n", label, text.trim_end()));
+        }
+    }
+    output.trim_end().to_string()
+}
+
+fn excerpt(text: &str) -> String {
+    if text.chars().count() <= EXCERPT_LIMIT {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(EXCERPT_LIMIT).collect();
+    format!("{}\n... (truncated)", head)
+}
@@ -0,0 +1,400 @@
+//! Token prices and spend.
+//!
+//! Prices are in USD per million tokens. `[[prices]]` entries are checked
+//! in order before the built-in list of common models, which only matches
+//! exact model IDs.
+
+use crate::config::PriceConfig;
+use crate::message::Usage;
+use crate::session::{self, Session};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    pub cache_read: f64,
+    pub cache_write: f64,
+}
+
+impl Price {
+    /// Cost of `usage` in USD.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input
+            + usage.output_tokens as f64 * self.output
+            + usage.cache_read_tokens as f64 * self.cache_read
+            + usage.cache_write_tokens as f64 * self.cache_write)
+            / 1_000_000.0
+    }
+}
+
+const fn price(input: f64, output: f64, cache_read: f64, cache_write: f64) -> Price {
+    Price {
+        input,
+        output,
+        cache_read,
+        cache_write,
+    }
+}
+
+/// List prices of common models by exact model ID; `[[prices]]` entries
+/// override them.
+const BUILTIN: &[(&str, Price)] = &[
+    // Anthropic list prices as of 2025-10, from
+    // https://docs.anthropic.com/en/docs/about-claude/pricing; cache writes
+    // at the 5-minute rate.
+    ("claude-opus-4-1-20250805", price(15.0, 75.0, 1.5, 18.75)),
+    ("claude-opus-4-1", price(15.0, 75.0, 1.5, 18.75)),
+    ("claude-opus-4-20250514", price(15.0, 75.0, 1.5, 18.75)),
+    ("claude-opus-4-0", price(15.0, 75.0, 1.5, 18.75)),
+    ("claude-sonnet-4-20250514", price(3.0, 15.0, 0.3, 3.75)),
+    ("claude-sonnet-4-0", price(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-7-sonnet-20250219", price(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-7-sonnet-latest", price(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-5-haiku-20241022", price(0.8, 4.0, 0.08, 1.0)),
+    ("claude-3-5-haiku-latest", price(0.8, 4.0, 0.08, 1.0)),
+    // OpenAI list prices as of 2025-10, from https://openai.com/api/pricing/;
+    // OpenAI does not charge for cache writes, so they cost the input price.
+    ("gpt-4o-2024-08-06", price(2.5, 10.0, 1.25, 2.5)),
+    ("gpt-4o", price(2.5, 10.0, 1.25, 2.5)),
+    ("gpt-4o-mini-2024-07-18", price(0.15, 0.6, 0.075, 0.15)),
+    ("gpt-4o-mini", price(0.15, 0.6, 0.075, 0.15)),
+];
+
+/// Configured prices, matched against model names, and the built-in ones.
+pub struct PriceTable {
+    entries: Vec<(glob::Pattern, Price)>,
+}
+
+impl PriceTable {
+    pub fn new(prices: &[PriceConfig]) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for entry in prices {
+            let pattern = glob::Pattern::new(&entry.model).map_err(|e| {
+                format!(
+                    "{}: invalid model pattern '{}': {}",
+                    entry.location, entry.model, e
+                )
+            })?;
+            let price = Price {
+                input: entry.input,
+                output: entry.output,
+                cache_read: entry.cache_read.unwrap_or(entry.input),
+                cache_write: entry.cache_write.unwrap_or(entry.input),
+            };
+            let amounts = [
+                price.input,
+                price.output,
+                price.cache_read,
+                price.cache_write,
+            ];
+            if amounts
+                .iter()
+                .any(|amount| !amount.is_finite() || *amount < 0.0)
+            {
+                return Err(format!(
+                    "{}: prices must be non-negative numbers",
+                    entry.location
+                ));
+            }
+            entries.push((pattern, price));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Price of the first `[[prices]]` entry matching `model`, else the
+    /// built-in price of exactly `model`.
+    pub fn lookup(&self, model: &str) -> Option<Price> {
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches(model))
+            .map(|(_, price)| *price)
+            .or_else(|| {
+                BUILTIN
+                    .iter()
+                    .find(|(id, _)| *id == model)
+                    .map(|(_, price)| *price)
+            })
+    }
+}
+
+/// `$0.0123` for display.
+pub fn format_usd(amount: f64) -> String {
+    format!("${:.4}", amount)
+}
+
+// === Spend Report ===
+
+#[derive(Default)]
+struct Spend {
+    calls: u64,
+    usage: Usage,
+    cost: f64,
+    /// Calls whose model has no price.
+    unpriced: u64,
+}
+
+impl Spend {
+    fn add(&mut self, other: &Spend) {
+        self.calls += other.calls;
+        self.usage.add(other.usage);
+        self.cost += other.cost;
+        self.unpriced += other.unpriced;
+    }
+
+    /// Cost in USD, or `None` when no call had a price.
+    fn cost_usd(&self) -> Option<f64> {
+        (self.unpriced < self.calls).then_some(self.cost)
+    }
+}
+
+/// Spend of all model calls in `sessions`, by UTC day and model.
+///
+/// Calls are costed at the price recorded when they ran; calls saved
+/// without one are priced from `prices` now.
+fn spend(sessions: &[Session], prices: &PriceTable) -> BTreeMap<(String, String), Spend> {
+    let mut rows: BTreeMap<(String, String), Spend> = BTreeMap::new();
+    let mut add = |at: u64, model: &str, usage: Usage, cost: Option<f64>| {
+        let row = rows
+            .entry((session::format_date(at), model.to_string()))
+            .or_default();
+        row.calls += 1;
+        row.usage.add(usage);
+        match cost.or_else(|| prices.lookup(model).map(|price| price.cost(&usage))) {
+            Some(cost) => row.cost += cost,
+            None => row.unpriced += 1,
+        }
+    };
+    for session in sessions {
+        for reply in &session.replies {
+            let model = if reply.model.is_empty() {
+                &session.model
+            } else {
+                &reply.model
+            };
+            let at = if reply.at == 0 {
+                session.updated_at
+            } else {
+                reply.at
+            };
+            add(at, model, reply.usage, reply.cost_usd);
+        }
+        // Summarizing calls made to compact the context.
+        for compaction in session.compactions.iter().filter(|c| !c.model.is_empty()) {
+            add(
+                compaction.at,
+                &compaction.model,
+                compaction.usage,
+                compaction.cost_usd,
+            );
+        }
+    }
+    rows
+}
+
+/// Spend table for `sessions cost`, one row per day and model.
+pub fn report(sessions: &[Session], prices: &PriceTable) -> String {
+    let rows = spend(sessions, prices);
+    if rows.is_empty() {
+        return "No model calls recorded".to_string();
+    }
+
+    let width = rows
+        .keys()
+        .map(|(_, model)| model.len())
+        .max()
+        .unwrap_or(0)
+        .max("Model".len());
+    let mut output = format!(
+        "{:<10}  {:<width$}  {:>6}  {:>12}  {:>12}  {:>10}\n",
+        "Date",
+        "Model",
+        "Calls",
+        "Input",
+        "Output",
+        "Cost",
+        width = width
+    );
+    let mut total = Spend::default();
+    let mut unpriced = Vec::new();
+    for ((day, model), row) in &rows {
+        let cost = row.cost_usd().map_or("-".to_string(), format_usd);
+        output.push_str(&format!(
+            "{:<10}  {:<width$}  {:>6}  {:>12}  {:>12}  {:>10}\n",
+            day,
+            model,
+            row.calls,
+            input_tokens(&row.usage),
+            row.usage.output_tokens,
+            cost,
+            width = width
+        ));
+        total.add(row);
+        if row.unpriced > 0 && !unpriced.contains(model) {
+            unpriced.push(model.clone());
+        }
+    }
+    output.push_str(&format!(
+        "{:<10}  {:<width$}  {:>6}  {:>12}  {:>12}  {:>10}\n",
+        "Total",
+        "",
+        total.calls,
+        input_tokens(&total.usage),
+        total.usage.output_tokens,
+        format_usd(total.cost),
+        width = width
+    ));
+    if !unpriced.is_empty() {
+        output.push_str(&format!(
+            "\nNot costed (no price): {}. Add [[prices]] entries to include them.\n",
+            unpriced.join(", ")
+        ));
+    }
+    output.trim_end().to_string()
+}
+
+/// Input tokens including cache reads and writes.
+fn input_tokens(usage: &Usage) -> u64 {
+    usage.input_tokens + usage.cache_read_tokens + usage.cache_write_tokens
+}
+
+/// [`report`] as JSON for `--output json`.
+pub fn report_json(sessions: &[Session], prices: &PriceTable) -> Value {
+    let mut total = Spend::default();
+    let rows: Vec<Value> = spend(sessions, prices)
+        .iter()
+        .map(|((day, model), row)| {
+            total.add(row);
+            json!({
+                "date": day,
+                "model": model,
+                "calls": row.calls,
+                "usage": row.usage,
+                "cost_usd": row.cost_usd(),
+                "unpriced_calls": row.unpriced,
+            })
+        })
+        .collect();
+    json!({
+        "rows": rows,
+        "total": {
+            "calls": total.calls,
+            "usage": total.usage,
+            "cost_usd": total.cost,
+            "unpriced_calls": total.unpriced,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(model: &str, input: f64, output: f64) -> PriceConfig {
+        PriceConfig {
+            model: model.to_string(),
+            input,
+            output,
+            cache_read: None,
+            cache_write: None,
+            location: "prices[0]".to_string(),
+        }
+    }
+
+    #[test]
+    fn builtin_prices_match_exact_model_ids_only() {
+        let table = PriceTable::new(&[]).unwrap();
+        let sonnet = table.lookup("claude-sonnet-4-20250514").unwrap();
+        assert_eq!(sonnet, price(3.0, 15.0, 0.3, 3.75));
+        assert!(table.lookup("claude-sonnet-4-20250514-preview").is_none());
+        assert!(table.lookup("claude-opus-4-2").is_none());
+        assert!(table.lookup("gpt-4o-mini-2024-07-18").is_some());
+    }
+
+    #[test]
+    fn configured_prices_come_first_and_may_use_globs() {
+        let table = PriceTable::new(&[entry("claude-sonnet-4*", 1.0, 2.0)]).unwrap();
+        let price = table.lookup("claude-sonnet-4-20250514").unwrap();
+        assert_eq!((price.input, price.output), (1.0, 2.0));
+        assert_eq!((price.cache_read, price.cache_write), (1.0, 1.0));
+
+        let err = PriceTable::new(&[entry("m", -1.0, 1.0)]).err().unwrap();
+        assert_eq!(err, "prices[0]: prices must be non-negative numbers");
+    }
+
+    #[test]
+    fn cost_is_per_million_tokens() {
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 500_000,
+            cache_read_tokens: 2_000_000,
+            cache_write_tokens: 0,
+        };
+        let cost = price(3.0, 15.0, 0.3, 3.75).cost(&usage);
+        assert!((cost - 11.1).abs() < 1e-9, "{}", cost);
+    }
+
+    #[test]
+    fn spend_is_grouped_by_day_and_model() {
+        use crate::config::Settings;
+        use crate::session::ReplyStats;
+
+        let day = 1_706_745_599; // 2024-01-31 23:59:59
+        let usage = |input_tokens, output_tokens| Usage {
+            input_tokens,
+            output_tokens,
+            ..Default::default()
+        };
+        let mut first = Session::new("a", &Settings::default());
+        first.replies = vec![
+            ReplyStats {
+                usage: usage(1_000, 100),
+                model: "claude-sonnet-4-20250514".to_string(),
+                at: day,
+                cost_usd: Some(0.5),
+                ..Default::default()
+            },
+            ReplyStats {
+                usage: usage(2_000, 200),
+                model: "local".to_string(),
+                at: day,
+                ..Default::default()
+            },
+        ];
+        let mut second = Session::new("b", &Settings::default());
+        second.model = "gpt-4o-mini".to_string();
+        second.updated_at = day + 1;
+        // Saved before replies recorded their model, time and cost.
+        second.replies = vec![ReplyStats {
+            usage: usage(1_000_000, 0),
+            ..Default::default()
+        }];
+        let sessions = [first, second];
+        let table = PriceTable::new(&[]).unwrap();
+
+        let rows = spend(&sessions, &table);
+        let costs: Vec<_> = rows
+            .iter()
+            .map(|((day, model), row)| (day.as_str(), model.as_str(), row.cost_usd()))
+            .collect();
+        assert_eq!(
+            costs,
+            [
+                ("2024-01-31", "claude-sonnet-4-20250514", Some(0.5)),
+                ("2024-01-31", "local", None),
+                ("2024-02-01", "gpt-4o-mini", Some(0.15)),
+            ]
+        );
+
+        let json = report_json(&sessions, &table);
+        assert_eq!(json["total"]["calls"], 3);
+        assert_eq!(json["total"]["unpriced_calls"], 1);
+        assert_eq!(json["total"]["usage"]["input_tokens"], 1_003_000);
+        assert!((json["total"]["cost_usd"].as_f64().unwrap() - 0.65).abs() < 1e-9);
+        let text = report(&sessions, &table);
+        assert!(text.contains("Not costed (no price): local."), "{}", text);
+        assert_eq!(report(&sessions[..0], &table), "No model calls recorded");
+    }
+}
@@ -0,0 +1,205 @@
+//! Live progress of a run.
+//!
+//! `run_command` returns only when the run ends, and lib_plugin_abi has no
+//! channel for progress, so `run --stream` writes events to stderr as JSON
+//! lines.
+//!
+//! `adi.host.events` is a proposed contract, not a service any host offers
+//! yet: a host that wants to show runs as they go would register a service
+//! under that ID, and `run` would then call its `emit` method with one JSON
+//! event per call instead of writing to stderr. Every event has a `type`
+//! and the `session` id:
+//!
+//! - `run_started` `{task, provider, model}`
+//! - `text_delta` `{text}`: assistant text as the model generates it
+//! - `tool_call_started` `{id, name, input}`
+//! - `tool_call_finished` `{id, name, is_error, duration_ms, output}`
+//! - `approval_requested` `{tool, input, preview}`
+//! - `approval_resolved` `{tool, decision}`: `once`, `session`, `deny` or `abort`
+//! - `usage` `{iteration, usage, total, cost_usd}` after each model call;
+//!   `total` and `cost_usd` are for the run so far
+//! - `run_finished` `{summary}`: the `data` of `run --output json`
+
+use crate::message::Usage;
+use lib_plugin_abi::ServiceHandle;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Proposed host service that would receive run events; see the module docs.
+pub const SERVICE_EVENTS: &str = "adi.host.events";
+
+/// A run event.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted {
+        task: &'a str,
+        provider: &'a str,
+        model: &'a str,
+    },
+    TextDelta {
+        text: &'a str,
+    },
+    ToolCallStarted {
+        id: &'a str,
+        name: &'a str,
+        input: &'a Value,
+    },
+    ToolCallFinished {
+        id: &'a str,
+        name: &'a str,
+        is_error: bool,
+        duration_ms: u64,
+        output: &'a str,
+    },
+    ApprovalRequested {
+        tool: &'a str,
+        input: &'a Value,
+        preview: Option<&'a str>,
+    },
+    ApprovalResolved {
+        tool: &'a str,
+        decision: &'a str,
+    },
+    Usage {
+        iteration: u64,
+        usage: Usage,
+        total: Usage,
+        cost_usd: Option<f64>,
+    },
+    RunFinished {
+        summary: Value,
+    },
+}
+
+/// Receives serialized events.
+pub trait Sink {
+    fn send(&mut self, event: &str);
+}
+
+/// Pick a service registered as `adi.host.events` if there is one, stderr
+/// with `--stream`.
+pub fn sink(host_events: Option<ServiceHandle>, stream: bool) -> Option<Box<dyn Sink>> {
+    match host_events {
+        Some(handle) => Some(Box::new(HostSink { handle })),
+        None if stream => Some(Box::new(StderrSink)),
+        None => None,
+    }
+}
+
+/// Emits the events of one session. Clones share the sink; without one,
+/// events are dropped.
+#[derive(Clone, Default)]
+pub struct Events {
+    inner: Option<Rc<Inner>>,
+}
+
+struct Inner {
+    session: String,
+    sink: RefCell<Box<dyn Sink>>,
+}
+
+impl Events {
+    pub fn new(sink: Option<Box<dyn Sink>>, session: &str) -> Self {
+        Self {
+            inner: sink.map(|sink| {
+                Rc::new(Inner {
+                    session: session.to_string(),
+                    sink: RefCell::new(sink),
+                })
+            }),
+        }
+    }
+
+    /// Whether anyone receives events, e.g. to decide whether to stream.
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn emit(&self, event: Event) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let Ok(mut value) = serde_json::to_value(&event) else {
+            return;
+        };
+        value["session"] = json!(inner.session);
+        inner.sink.borrow_mut().send(&value.to_string());
+    }
+}
+
+// === Sinks ===
+
+struct HostSink {
+    handle: ServiceHandle,
+}
+
+impl Sink for HostSink {
+    fn send(&mut self, event: &str) {
+        // Progress is best effort; a host that fails to take an event must
+        // not fail the run.
+        let _ = unsafe { self.handle.invoke("emit", event) };
+    }
+}
+
+struct StderrSink;
+
+impl Sink for StderrSink {
+    fn send(&mut self, event: &str) {
+        let _ = writeln!(std::io::stderr().lock(), "{}", event);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Keeps the events it receives, parsed.
+    #[derive(Clone, Default)]
+    pub(crate) struct Collect(pub Rc<RefCell<Vec<Value>>>);
+
+    impl Sink for Collect {
+        fn send(&mut self, event: &str) {
+            self.0
+                .borrow_mut()
+                .push(serde_json::from_str(event).unwrap());
+        }
+    }
+
+    #[test]
+    fn events_carry_their_type_and_session() {
+        let collect = Collect::default();
+        let events = Events::new(Some(Box::new(collect.clone())), "s1");
+        assert!(events.is_enabled());
+        events.clone().emit(Event::ToolCallFinished {
+            id: "call_1",
+            name: "shell",
+            is_error: false,
+            duration_ms: 5,
+            output: "ok",
+        });
+        events.emit(Event::ApprovalResolved {
+            tool: "shell",
+            decision: "once",
+        });
+
+        assert_eq!(
+            *collect.0.borrow(),
+            [
+                json!({"type": "tool_call_finished", "session": "s1", "id": "call_1",
+                       "name": "shell", "is_error": false, "duration_ms": 5, "output": "ok"}),
+                json!({"type": "approval_resolved", "session": "s1", "tool": "shell",
+                       "decision": "once"}),
+            ]
+        );
+
+        let silent = Events::new(None, "s2");
+        assert!(!silent.is_enabled());
+        silent.emit(Event::TextDelta { text: "dropped" });
+        assert!(sink(None, false).is_none());
+        assert!(sink(None, true).is_some());
+    }
+}
@@ -0,0 +1,507 @@
+//! Session transcripts rendered as Markdown, JSON or HTML.
+
+use crate::agent::{format_duration, RunOutcome};
+use crate::cost;
+use crate::message::{ContentBlock, Role, Usage};
+use crate::session::{self, Session};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Characters of each tool output included in an export.
+const OUTPUT_LIMIT: usize = 2000;
+
+/// Export file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "md" | "markdown" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            "html" => Ok(Self::Html),
+            _ => Err(format!(
+                "Unknown export format: {}. Use 'md', 'json' or 'html'",
+                value
+            )),
+        }
+    }
+}
+
+/// Render `session` in `format`.
+pub fn export(session: &Session, format: Format) -> String {
+    let turns = turns(session);
+    match format {
+        Format::Markdown => markdown(session, &turns),
+        Format::Json => {
+            serde_json::to_string_pretty(&document(session, &turns)).unwrap_or_default()
+        }
+        Format::Html => html(session, &turns),
+    }
+}
+
+/// The JSON export of `session` as a value.
+pub fn document_json(session: &Session) -> Value {
+    document(session, &turns(session))
+}
+
+// === Turns ===
+
+/// A user message and the model's reply to it.
+#[derive(Debug, Serialize)]
+struct Turn {
+    turn: usize,
+    user: Option<String>,
+    assistant: Option<Reply>,
+}
+
+#[derive(Debug, Serialize)]
+struct Reply {
+    text: Option<String>,
+    tool_calls: Vec<Call>,
+    usage: Option<Usage>,
+    duration_ms: Option<u64>,
+    cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Call {
+    name: String,
+    input: Value,
+    output: Option<String>,
+    /// Characters cut from `output`.
+    truncated: usize,
+    is_error: bool,
+}
+
+fn turns(session: &Session) -> Vec<Turn> {
+    let results: Vec<(&str, &str, bool)> = session
+        .messages
+        .iter()
+        .flat_map(|m| &m.content)
+        .filter_map(|block| match block {
+            ContentBlock::ToolResult {
+                tool_use_id,
+                content,
+                is_error,
+            } => Some((tool_use_id.as_str(), content.as_str(), *is_error)),
+            _ => None,
+        })
+        .collect();
+
+    let mut turns: Vec<Turn> = Vec::new();
+    let mut replies = session.replies.iter();
+    for message in &session.messages {
+        match message.role {
+            Role::User => {
+                let text = message.text();
+                turns.push(Turn {
+                    turn: turns.len() + 1,
+                    user: (!text.is_empty()).then_some(text),
+                    assistant: None,
+                });
+            }
+            Role::Assistant => {
+                let stats = replies.next();
+                let tool_calls = message
+                    .tool_calls()
+                    .into_iter()
+                    .map(|call| {
+                        let result = results.iter().find(|(id, _, _)| *id == call.id);
+                        let (output, truncated) = match result {
+                            Some((_, content, _)) => {
+                                let (text, cut) = truncate(content);
+                                (Some(text), cut)
+                            }
+                            None => (None, 0),
+                        };
+                        Call {
+                            name: call.name,
+                            input: call.input,
+                            output,
+                            truncated,
+                            is_error: result.is_some_and(|(_, _, is_error)| *is_error),
+                        }
+                    })
+                    .collect();
+                let text = message.text();
+                let reply = Reply {
+                    text: (!text.is_empty()).then_some(text),
+                    tool_calls,
+                    usage: stats.map(|s| s.usage),
+                    duration_ms: stats.map(|s| s.duration_ms),
+                    cost_usd: stats.and_then(|s| s.cost_usd),
+                };
+                match turns.last_mut() {
+                    Some(turn) if turn.assistant.is_none() => turn.assistant = Some(reply),
+                    _ => turns.push(Turn {
+                        turn: turns.len() + 1,
+                        user: None,
+                        assistant: Some(reply),
+                    }),
+                }
+            }
+        }
+    }
+    turns
+}
+
+/// The first `OUTPUT_LIMIT` characters of `text` and how many were cut.
+fn truncate(text: &str) -> (String, usize) {
+    let total = text.chars().count();
+    if total <= OUTPUT_LIMIT {
+        return (text.to_string(), 0);
+    }
+    (
+        text.chars().take(OUTPUT_LIMIT).collect(),
+        total - OUTPUT_LIMIT,
+    )
+}
+
+fn outcome(session: &Session) -> String {
+    match &session.summary.outcome {
+        RunOutcome::Failed { error } => format!("failed: {}", error),
+        other => other.label().to_string(),
+    }
+}
+
+fn usage_text(usage: &Usage) -> String {
+    format!("{} in / {} out", usage.input_tokens, usage.output_tokens)
+}
+
+// === JSON ===
+
+fn document(session: &Session, turns: &[Turn]) -> Value {
+    let summary = &session.summary;
+    json!({
+        "id": session.id,
+        "task": summary.task,
+        "outcome": summary.outcome,
+        "provider": session.provider,
+        "model": session.model,
+        "created_at": session.created_at,
+        "updated_at": session.updated_at,
+        "parent": session.parent,
+        "forked_at": session.forked_at,
+        "iterations": summary.iterations,
+        "usage": summary.usage,
+        "duration_ms": summary.duration_ms,
+        "budget": summary.budget,
+        "cost_usd": summary.cost_usd,
+        "cost_cap": summary.cost_cap,
+        "final_answer": summary.final_answer,
+        "compactions": session.compactions,
+        "turns": turns,
+    })
+}
+
+// === Markdown ===
+
+fn markdown(session: &Session, turns: &[Turn]) -> String {
+    let summary = &session.summary;
+    let mut out = format!("# Agent session {}\n\n", session.id);
+    out.push_str(&format!("- **Task:** {}\n", summary.task));
+    out.push_str(&format!("- **Outcome:** {}\n", outcome(session)));
+    out.push_str(&format!(
+        "- **Model:** {} ({})\n",
+        session.model, session.provider
+    ));
+    out.push_str(&format!(
+        "- **Started:** {}\n",
+        session::format_time(session.created_at)
+    ));
+    out.push_str(&format!("- **Tokens:** {}\n", usage_text(&summary.usage)));
+    out.push_str(&format!(
+        "- **Duration:** {}\n",
+        format_duration(summary.duration_ms)
+    ));
+    if let Some(cost) = summary.cost_usd {
+        out.push_str(&format!("- **Cost:** {}\n", cost::format_usd(cost)));
+    }
+    if let (Some(parent), Some(turn)) = (&session.parent, session.forked_at) {
+        out.push_str(&format!("- **Forked from:** {} at turn {}\n", parent, turn));
+    }
+
+    for turn in turns {
+        out.push_str(&format!("\n## Turn {}\n", turn.turn));
+        if let Some(text) = &turn.user {
+            out.push_str(&format!("\n**User**\n\n{}\n", text.trim_end()));
+        }
+        let Some(reply) = &turn.assistant else {
+            continue;
+        };
+        out.push_str("\n**Assistant**");
+        if let Some(usage) = &reply.usage {
+            out.push_str(&format!(" · {}", usage_text(usage)));
+        }
+        if let Some(ms) = reply.duration_ms {
+            out.push_str(&format!(" · {}", format_duration(ms)));
+        }
+        if let Some(cost) = reply.cost_usd {
+            out.push_str(&format!(" · {}", cost::format_usd(cost)));
+        }
+        out.push('\n');
+        if let Some(text) = &reply.text {
+            out.push_str(&format!("\n{}\n", text.trim_end()));
+        }
+        for call in &reply.tool_calls {
+            let input = serde_json::to_string_pretty(&call.input).unwrap_or_default();
+            out.push_str(&format!(
+                "\n**Tool call:** `{}`\n\n{}",
+                call.name,
+                fenced(&input, "json")
+            ));
+            if let Some(output) = &call.output {
+                let label = if call.is_error { "Error" } else { "Output" };
+                out.push_str(&format!("\n{}:\n\n{}", label, fenced(output, "")));
+                if call.truncated > 0 {
+                    out.push_str(&format!(
+                        "\n_({} more characters truncated)_\n",
+                        call.truncated
+                    ));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// `text` in a code fence longer than any backtick run inside it.
+fn fenced(text: &str, language: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}{}\n{}\n{}\n", fence, language, text.trim_end(), fence)
+}
+
+// === HTML ===
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:960px;margin:2em auto;\
+padding:0 1em;color:#1f2328}h2{border-bottom:1px solid #d0d7de;padding-bottom:.3em}\
+pre{background:#f6f8fa;padding:.8em;overflow-x:auto;white-space:pre-wrap}\
+.meta{color:#59636e;font-size:.9em}.role{font-weight:600;margin-top:1em}\
+.error{border-left:3px solid #cf222e}";
+
+fn html(session: &Session, turns: &[Turn]) -> String {
+    let summary = &session.summary;
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Agent session {}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape(&session.id),
+        STYLE
+    );
+    out.push_str(&format!(
+        "<h1>Agent session {}</h1>\n<ul>\n",
+        escape(&session.id)
+    ));
+    let mut fields = vec![
+        ("Task", summary.task.clone()),
+        ("Outcome", outcome(session)),
+        ("Model", format!("{} ({})", session.model, session.provider)),
+        ("Started", session::format_time(session.created_at)),
+        ("Tokens", usage_text(&summary.usage)),
+        ("Duration", format_duration(summary.duration_ms)),
+    ];
+    if let Some(cost) = summary.cost_usd {
+        fields.push(("Cost", cost::format_usd(cost)));
+    }
+    if let (Some(parent), Some(turn)) = (&session.parent, session.forked_at) {
+        fields.push(("Forked from", format!("{} at turn {}", parent, turn)));
+    }
+    for (label, value) in fields {
+        out.push_str(&format!(
+            "<li><strong>{}:</strong> {}</li>\n",
+            label,
+            escape(&value)
+        ));
+    }
+    out.push_str("</ul>\n");
+
+    for turn in turns {
+        out.push_str(&format!("<h2>Turn {}</h2>\n", turn.turn));
+        if let Some(text) = &turn.user {
+            out.push_str(&format!(
+                "<div class=\"role\">User</div>\n<pre>{}</pre>\n",
+                escape(text.trim_end())
+            ));
+        }
+        let Some(reply) = &turn.assistant else {
+            continue;
+        };
+        let mut meta = Vec::new();
+        if let Some(usage) = &reply.usage {
+            meta.push(usage_text(usage));
+        }
+        if let Some(ms) = reply.duration_ms {
+            meta.push(format_duration(ms));
+        }
+        if let Some(cost) = reply.cost_usd {
+            meta.push(cost::format_usd(cost));
+        }
+        out.push_str(&format!(
+            "<div class=\"role\">Assistant <span class=\"meta\">{}</span></div>\n",
+            escape(&meta.join(" · "))
+        ));
+        if let Some(text) = &reply.text {
+            out.push_str(&format!("<pre>{}</pre>\n", escape(text.trim_end())));
+        }
+        for call in &reply.tool_calls {
+            let input = serde_json::to_string_pretty(&call.input).unwrap_or_default();
+            out.push_str(&format!(
+                "<div class=\"role\">Tool call: <code>{}</code></div>\n<pre>{}</pre>\n",
+                escape(&call.name),
+                escape(&input)
+            ));
+            if let Some(output) = &call.output {
+                let (label, class) = if call.is_error {
+                    ("Error", " class=\"error\"")
+                } else {
+                    ("Output", "")
+                };
+                out.push_str(&format!(
+                    "<details open><summary>{}</summary><pre{}>{}</pre>",
+                    label,
+                    class,
+                    escape(output.trim_end())
+                ));
+                if call.truncated > 0 {
+                    out.push_str(&format!(
+                        "<p class=\"meta\">({} more characters truncated)</p>",
+                        call.truncated
+                    ));
+                }
+                out.push_str("</details>\n");
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use crate::message::Message;
+    use crate::session::ReplyStats;
+
+    /// A session with one tool call whose output is `output`, then an answer.
+    fn session(output: &str) -> Session {
+        let mut session = Session::new("Show <main.rs>", &Settings::default());
+        session.id = "20240131-235959-1a2b".to_string();
+        session.messages.push(Message::assistant(vec![
+            ContentBlock::Text {
+                text: "Reading it.".to_string(),
+            },
+            ContentBlock::ToolUse {
+                id: "call_1".to_string(),
+                name: "read_file".to_string(),
+                input: json!({"path": "main.rs"}),
+            },
+        ]));
+        session.messages.push(Message {
+            role: Role::User,
+            content: vec![ContentBlock::ToolResult {
+                tool_use_id: "call_1".to_string(),
+                content: output.to_string(),
+                is_error: false,
+            }],
+        });
+        session
+            .messages
+            .push(Message::assistant(vec![ContentBlock::Text {
+                text: "It prints a & b.".to_string(),
+            }]));
+        session.replies = vec![
+            ReplyStats {
+                usage: Usage {
+                    input_tokens: 10,
+                    output_tokens: 4,
+                    ..Default::default()
+                },
+                duration_ms: 1500,
+                cost_usd: Some(0.25),
+                ..Default::default()
+            },
+            ReplyStats::default(),
+        ];
+        session.summary.outcome = RunOutcome::Completed;
+        session.summary.cost_usd = Some(0.25);
+        session
+    }
+
+    #[test]
+    fn json_export_pairs_calls_with_their_output() {
+        let long = "x".repeat(OUTPUT_LIMIT + 5);
+        let document = document_json(&session(&long));
+
+        assert_eq!(document["id"], "20240131-235959-1a2b");
+        assert_eq!(document["outcome"]["status"], "completed");
+        let turns = document["turns"].as_array().unwrap();
+        assert_eq!(turns.len(), 2);
+        assert_eq!(turns[0]["user"], "Show <main.rs>");
+        let call = &turns[0]["assistant"]["tool_calls"][0];
+        assert_eq!(call["name"], "read_file");
+        assert_eq!(call["output"].as_str().unwrap().len(), OUTPUT_LIMIT);
+        assert_eq!(call["truncated"], 5);
+        assert_eq!(turns[0]["assistant"]["usage"]["input_tokens"], 10);
+        // Tool results are not a user message of their own.
+        assert_eq!(turns[1]["user"], Value::Null);
+        assert_eq!(turns[1]["assistant"]["text"], "It prints a & b.");
+    }
+
+    #[test]
+    fn markdown_export_fences_output_safely() {
+        let markdown = export(&session("```rust\nfn main() {}\n```"), Format::Markdown);
+
+        assert!(markdown.starts_with("# Agent session 20240131-235959-1a2b\n"));
+        assert!(markdown.contains("- **Outcome:** completed\n"));
+        assert!(markdown.contains("- **Cost:** $0.2500\n"));
+        assert!(markdown.contains("\n## Turn 2\n"));
+        assert!(markdown.contains("**Assistant** · 10 in / 4 out · 1.5s"));
+        assert!(markdown.contains("\n````\n```rust\nfn main() {}\n```\n````\n"));
+    }
+
+    #[test]
+    fn html_export_escapes_the_transcript() {
+        let html = export(&session("<script>alert('x')</script>"), Format::Html);
+
+        assert!(html.contains("<li><strong>Task:</strong> Show &lt;main.rs&gt;</li>"));
+        assert!(html.contains("&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<pre>It prints a &amp; b.</pre>"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+
+    #[test]
+    fn formats_parse() {
+        assert_eq!(Format::parse("md"), Ok(Format::Markdown));
+        assert_eq!(Format::parse("markdown"), Ok(Format::Markdown));
+        assert_eq!(Format::parse("html"), Ok(Format::Html));
+        assert!(Format::parse("pdf")
+            .unwrap_err()
+            .contains("Unknown export format"));
+    }
+}
@@ -2,16 +2,45 @@
 //!
 //! Provides CLI commands for autonomous LLM agents.
 
+mod agent;
+mod approval;
+mod cancel;
+mod cli;
+mod config;
+mod context;
+mod cost;
+mod events;
+mod export;
+mod message;
+mod output;
+mod policy;
+mod provider;
+mod run;
+mod service;
+mod session;
+mod suggest;
+mod tools;
+
 use abi_stable::std_types::{ROption, RResult, RStr, RString, RVec};
+use agent::RunOutcome;
+use cancel::CancelToken;
+use config::{Config, ConfigFile, ProviderKind};
+use cost::PriceTable;
 use lib_plugin_abi::{
     PluginContext, PluginInfo, PluginVTable, ServiceDescriptor, ServiceError, ServiceHandle,
     ServiceMethod, ServiceVTable, ServiceVersion,
 };
+use output::{CliError, OutputFormat, Reply};
+use policy::Policy;
+use run::RunRequest;
+use session::{Session, SessionStore};
 
 /// Plugin-specific CLI service ID
 const SERVICE_CLI: &str = "adi.agent-loop.cli";
 use serde_json::json;
 use std::ffi::c_void;
+use std::time::Duration;
+use tools::ToolRegistry;
 
 // === Plugin VTable Implementation ===
 
@@ -50,13 +79,37 @@ extern "C" fn plugin_init(ctx: *mut PluginContext) -> i32 {
             return code;
         }
 
+        // Register agent service
+        let agent_descriptor = ServiceDescriptor::new(
+            service::SERVICE_AGENT,
+            ServiceVersion::new(1, 0, 0),
+            "adi.agent-loop",
+        )
+        .with_description("Agent runs, sessions and tools for other plugins");
+
+        let agent_handle = ServiceHandle::new(
+            service::SERVICE_AGENT,
+            ctx as *const c_void,
+            &AGENT_SERVICE_VTABLE as *const ServiceVTable,
+        );
+
+        if let Err(code) = host.register_svc(agent_descriptor, agent_handle) {
+            host.error(&format!("Failed to register agent service: {}", code));
+            return code;
+        }
+
         host.info("ADI Agent Loop plugin initialized");
     }
 
     0
 }
 
-extern "C" fn plugin_cleanup(_ctx: *mut PluginContext) {}
+extern "C" fn plugin_cleanup(_ctx: *mut PluginContext) {
+    // Stop in-flight runs and kill the subprocesses they started, then give
+    // the service's run threads a moment to save their sessions.
+    cancel::cancel_all();
+    service::join_runs(Duration::from_secs(5));
+}
 
 // === Plugin Entry Point ===
 
@@ -81,24 +134,31 @@ static CLI_SERVICE_VTABLE: ServiceVTable = ServiceVTable {
 };
 
 extern "C" fn cli_invoke(
-    _handle: *const c_void,
+    handle: *const c_void,
     method: RStr<'_>,
     args: RStr<'_>,
 ) -> RResult<RString, ServiceError> {
+    // The service was registered with the plugin context as its handle.
+    let ctx = unsafe { &*(handle as *const PluginContext) };
     match method.as_str() {
         "run_command" => {
-            let result = run_cli_command(args.as_str());
+            let result = run_cli_command(ctx, args.as_str());
             match result {
                 Ok(output) => RResult::ROk(RString::from(output)),
                 Err(e) => RResult::RErr(ServiceError::invocation_error(e)),
             }
         }
         "list_commands" => {
-            let commands = json!([
-                {"name": "run", "description": "Run agent with a task", "usage": "run <task> [--max-iterations <n>] [--yes]"},
-                {"name": "config", "description": "Manage configuration", "usage": "config [show|set <key> <value>]"},
-                {"name": "tools", "description": "List available tools", "usage": "tools [list]"}
-            ]);
+            let commands: Vec<serde_json::Value> = cli::COMMANDS
+                .iter()
+                .map(|command| {
+                    json!({
+                        "name": command.name,
+                        "description": command.description,
+                        "usage": cli::usage(command),
+                    })
+                })
+                .collect();
             RResult::ROk(RString::from(
                 serde_json::to_string(&commands).unwrap_or_default(),
             ))
@@ -116,7 +176,42 @@ extern "C" fn cli_list_methods(_handle: *const c_void) -> RVec<ServiceMethod> {
     .collect()
 }
 
-fn run_cli_command(context_json: &str) -> Result<String, String> {
+// === Agent Service VTable ===
+
+static AGENT_SERVICE_VTABLE: ServiceVTable = ServiceVTable {
+    invoke: agent_invoke,
+    list_methods: agent_list_methods,
+};
+
+extern "C" fn agent_invoke(
+    handle: *const c_void,
+    method: RStr<'_>,
+    args: RStr<'_>,
+) -> RResult<RString, ServiceError> {
+    // The service was registered with the plugin context as its handle.
+    let ctx = unsafe { &*(handle as *const PluginContext) };
+    match service::call(ctx, method.as_str(), args.as_str()) {
+        Some(Ok(result)) => RResult::ROk(RString::from(result.to_string())),
+        Some(Err(e)) => RResult::RErr(ServiceError::invocation_error(
+            json!({"code": e.code, "message": e.message}).to_string(),
+        )),
+        None => RResult::RErr(ServiceError::method_not_found(method.as_str())),
+    }
+}
+
+extern "C" fn agent_list_methods(_handle: *const c_void) -> RVec<ServiceMethod> {
+    service::methods()
+        .into_iter()
+        .map(|method| {
+            ServiceMethod::new(method.name)
+                .with_description(method.description)
+                .with_parameters_schema(&method.input.to_string())
+                .with_returns_schema(&method.output.to_string())
+        })
+        .collect()
+}
+
+fn run_cli_command(ctx: &PluginContext, context_json: &str) -> Result<String, String> {
     let context: serde_json::Value =
         serde_json::from_str(context_json).map_err(|e| format!("Invalid context: {}", e))?;
 
@@ -131,126 +226,508 @@ fn run_cli_command(context_json: &str) -> Result<String, String> {
         })
         .unwrap_or_default();
 
-    let subcommand = args.first().map(|s| s.as_str()).unwrap_or("");
-    let cmd_args: Vec<&str> = args.iter().skip(1).map(|s| s.as_str()).collect();
-
-    // Parse options from remaining args (--key value format)
-    let mut options = serde_json::Map::new();
-    let mut i = 0;
-    while i < cmd_args.len() {
-        if cmd_args[i].starts_with("--") {
-            let key = cmd_args[i].trim_start_matches("--");
-            if i + 1 < cmd_args.len() && !cmd_args[i + 1].starts_with("--") {
-                options.insert(key.to_string(), json!(cmd_args[i + 1]));
-                i += 2;
-            } else {
-                options.insert(key.to_string(), json!(true));
-                i += 1;
-            }
-        } else {
-            i += 1;
+    // Read `--output` first so that argument errors honour it too.
+    let format = match cli::output_option(&args) {
+        Some(value) => OutputFormat::parse(value)?,
+        None => OutputFormat::Text,
+    };
+    let mut command = args[cli::global_prefix(&args)..]
+        .first()
+        .cloned()
+        .unwrap_or_default();
+    let result = match cli::parse(&args) {
+        Ok(cli::Parsed::Help(help)) => Ok(Reply::new(help.clone(), json!({ "help": help }))),
+        Ok(cli::Parsed::Command(invocation)) => {
+            command = invocation.command.join(" ");
+            dispatch(ctx, &invocation)
         }
-    }
+        Err(e) => Err(e.into()),
+    };
+    output::render(format, &command, result)
+}
 
-    // Get positional args (non-option args after subcommand)
-    let positional: Vec<&str> = cmd_args
+fn dispatch(ctx: &PluginContext, invocation: &cli::Invocation) -> Result<Reply, CliError> {
+    // Handlers see the subcommand path followed by the positional arguments.
+    let positional: Vec<&str> = invocation.command[1..]
         .iter()
-        .filter(|a| !a.starts_with("--"))
         .copied()
+        .chain(invocation.args.iter().map(String::as_str))
         .collect();
-
-    let options_value = serde_json::Value::Object(options);
-
-    match subcommand {
-        "run" => cmd_run(&positional, &options_value),
-        "config" => cmd_config(&positional),
-        "tools" => cmd_tools(&positional),
-        "" => {
-            let help = "ADI Agent Loop - Autonomous LLM agent with tool execution\n\n\
-                        Commands:\n  \
-                        run      Run agent with a task\n  \
-                        config   Manage configuration\n  \
-                        tools    List available tools\n\n\
-                        Usage: adi run adi.agent-loop <command> [args]";
-            Ok(help.to_string())
-        }
-        _ => Err(format!("Unknown command: {}", subcommand)),
+    let options = &invocation.options;
+
+    match invocation.command[0] {
+        "run" => cmd_run(ctx, &positional, options),
+        "config" => cmd_config(&positional, options),
+        "tools" => cmd_tools(ctx, &positional),
+        "sessions" => cmd_sessions(&positional, options),
+        command => Err(format!("Unknown command: {}", command).into()),
     }
 }
 
 // === Command Implementations ===
 
-fn cmd_run(args: &[&str], options: &serde_json::Value) -> Result<String, String> {
-    if args.is_empty() {
-        return Err("Missing task. Usage: run <task> [--max-iterations <n>] [--yes]".to_string());
+fn cmd_run(
+    ctx: &PluginContext,
+    args: &[&str],
+    options: &serde_json::Value,
+) -> Result<Reply, CliError> {
+    let resume = options.get("resume").and_then(|v| v.as_str());
+    let task = args.first().copied();
+    if task.is_none() && resume.is_none() {
+        return Err(cli::ArgError::MissingArgument {
+            command: "run".to_string(),
+            name: "task",
+        }
+        .into());
     }
 
-    let task = args[0];
-    let max_iterations = options
-        .get("max-iterations")
+    // `--timeout 30m` is shorthand for `--run-timeout-ms 1800000`.
+    let run_timeout = options
+        .get("timeout")
         .and_then(|v| v.as_str())
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(50u64);
-    let auto_approve = options
-        .get("yes")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
-
-    // For now, return a message indicating the agent would run
-    // Full implementation requires LLM provider configuration
-    let mut output = String::new();
-    output.push_str(&format!("Agent Task: {}\n", task));
-    output.push_str(&format!("Max Iterations: {}\n", max_iterations));
-    output.push_str(&format!("Auto-approve: {}\n\n", auto_approve));
-    output.push_str("Note: Full agent execution requires LLM provider configuration.\n");
-    output.push_str("Configure your LLM provider in ~/.config/adi/agent.toml");
-
-    Ok(output)
+        .map(|value| {
+            config::parse_duration_ms(value)
+                .map_err(|e| CliError::invalid_argument(format!("--timeout: {}", e)))
+        })
+        .transpose()?
+        .map(|ms| ms.to_string());
+
+    // Every setting can be overridden per invocation, e.g. `--max-iterations 5`.
+    let mut settings: Vec<(String, String)> = config::schema::SETTINGS
+        .iter()
+        .filter_map(|spec| {
+            options
+                .get(spec.key.replace('_', "-").as_str())
+                .and_then(|v| v.as_str())
+                .map(|value| (spec.key.to_string(), value.to_string()))
+        })
+        .collect();
+    if let Some(ms) = run_timeout {
+        settings.push(("run_timeout_ms".to_string(), ms));
+    }
+    if options.get("mock-script").is_some() && options.get("provider").is_none() {
+        settings.push((
+            "provider".to_string(),
+            ProviderKind::Mock.as_str().to_string(),
+        ));
+    }
+    // A bad flag value is an argument error, not a config error.
+    for (key, value) in &settings {
+        config::schema::lookup(key)
+            .and_then(|spec| spec.check(value))
+            .map_err(|e| {
+                CliError::invalid_argument(format!("--{}: {}", key.replace('_', "-"), e))
+            })?;
+    }
+    let request = RunRequest {
+        task: task.map(String::from),
+        resume: resume.map(String::from),
+        settings,
+        auto_approve: options
+            .get("yes")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+    };
+
+    let stream = options.get("stream").is_some();
+    let sink = events::sink(ctx.host().lookup_svc(events::SERVICE_EVENTS), stream);
+    let prompter = approval::prompter(ctx.host().lookup_svc(approval::SERVICE_PROMPT));
+    let services = tools::provider_tools(ctx);
+    let run = run::prepare(&request, services, prompter, sink, CancelToken::new())?;
+    for conflict in run.conflicts() {
+        ctx.host().error(conflict);
+    }
+    let session = run.execute()?;
+
+    let mut output = session.summary.render();
+    output.push_str(&format!("\n\nSession: {}", session.id));
+    if session.summary.outcome != RunOutcome::Completed {
+        output.push_str(&format!(" (continue with: run --resume {})", session.id));
+    }
+    let mut data = output::run_summary(&session.summary);
+    data["session"] = json!(session.id);
+    Ok(Reply::new(output, data))
 }
 
-fn cmd_config(args: &[&str]) -> Result<String, String> {
+fn cmd_config(args: &[&str], options: &serde_json::Value) -> Result<Reply, CliError> {
     let subcommand = args.first().copied().unwrap_or("show");
 
     match subcommand {
         "show" => {
+            let config = load_config()?;
+            let show_origin = options.get("origin").is_some();
+
             let mut output = String::from("Current configuration:\n\n");
-            output.push_str("  model: claude-sonnet-4-20250514\n");
-            output.push_str("  max_iterations: 50\n");
-            output.push_str("  max_tokens: 100000\n");
-            output.push_str("  timeout_ms: 120000\n");
-            Ok(output.trim_end().to_string())
+            let mut settings = Vec::new();
+            for (key, value) in config.settings.entries() {
+                let origin = config.origin(key);
+                settings.push(json!({
+                    "key": key,
+                    "value": config.settings.json_value(key),
+                    "origin": origin.to_string(),
+                    "source": origin.source(),
+                }));
+                let origin = if show_origin {
+                    origin.detail()
+                } else {
+                    origin.to_string()
+                };
+                output.push_str(&format!("  {}: {}  ({})\n", key, value, origin));
+            }
+
+            output.push_str(&format!("\nUser config: {}\n", config.user_path.display()));
+            match &config.project_path {
+                Some(path) => output.push_str(&format!("Project config: {}\n", path.display())),
+                None => output.push_str("Project config: (none found)\n"),
+            }
+            let data = json!({
+                "settings": settings,
+                "user_config": config.user_path,
+                "project_config": config.project_path,
+            });
+            Ok(Reply::new(output.trim_end(), data))
         }
         "set" => {
-            if args.len() < 3 {
-                return Err("Usage: config set <key> <value>".to_string());
-            }
             let key = args[1];
             let value = args[2];
-            Ok(format!("Set {} = {}", key, value))
+            let path = if options.get("project").is_some() {
+                config::project_config_path()?
+            } else {
+                config::user_config_path()?
+            };
+            if options.get("project").is_some() && config::schema::USER_ONLY.contains(&key) {
+                return Err(CliError::invalid_argument(format!(
+                    "{} can only be set in the user config; drop --project",
+                    key
+                )));
+            }
+            let mut file = ConfigFile::load(&path).map_err(CliError::invalid_config)?;
+            file.set(key, value).map_err(CliError::invalid_argument)?;
+            file.save()?;
+            Ok(Reply::new(
+                format!("Set {} = {} in {}", key, value, path.display()),
+                json!({"key": key, "value": value, "path": path}),
+            ))
         }
+        "unset" => {
+            let key = args[1];
+            let path = if options.get("project").is_some() {
+                config::project_config_path()?
+            } else {
+                config::user_config_path()?
+            };
+            let mut file = ConfigFile::load(&path).map_err(CliError::invalid_config)?;
+            let removed = file.unset(key).map_err(CliError::invalid_argument)?;
+            if removed {
+                file.save()?;
+            }
+            let text = if removed {
+                format!("Removed {} from {}", key, path.display())
+            } else {
+                format!("{} is not set in {}", key, path.display())
+            };
+            Ok(Reply::new(
+                text,
+                json!({"key": key, "removed": removed, "path": path}),
+            ))
+        }
+        "validate" => {
+            let paths = match args.get(1) {
+                Some(path) => vec![std::path::PathBuf::from(path)],
+                None => std::iter::once(config::user_config_path()?)
+                    .chain(config::find_project_config())
+                    .filter(|path| path.is_file())
+                    .collect(),
+            };
+            if paths.is_empty() {
+                return Ok(Reply::new(
+                    "No config files to validate",
+                    json!({"files": []}),
+                ));
+            }
+
+            let mut problems = Vec::new();
+            let mut output = String::new();
+            for path in &paths {
+                let found = config::validate_file(path)?;
+                if found.is_empty() {
+                    output.push_str(&format!("{}: OK\n", path.display()));
+                }
+                problems.extend(found);
+            }
+            if problems.is_empty() {
+                Ok(Reply::new(output.trim_end(), json!({ "files": paths })))
+            } else {
+                Err(CliError::invalid_config(problems.join("\n")))
+            }
+        }
+        "policy" => cmd_config_policy(&args[1..]),
         _ => Err(format!(
-            "Unknown config subcommand: {}. Use 'show' or 'set'",
+            "Unknown config subcommand: {}. Use 'show', 'set', 'unset', 'validate' or 'policy'",
             subcommand
+        )
+        .into()),
+    }
+}
+
+fn cmd_config_policy(args: &[&str]) -> Result<Reply, CliError> {
+    const USAGE: &str = "Usage: config policy test <tool> [json-args]";
+    if args.first() != Some(&"test") {
+        return Err(CliError::invalid_argument(USAGE));
+    }
+    let Some(tool) = args.get(1).copied() else {
+        return Err(CliError::invalid_argument(USAGE));
+    };
+    let input: serde_json::Value = match args.get(2) {
+        Some(json) => serde_json::from_str(json)
+            .map_err(|e| CliError::invalid_argument(format!("Invalid JSON args: {}", e)))?,
+        None => json!({}),
+    };
+
+    let config = load_config()?;
+    let policy = Policy::new(&config.policy, config.settings.approval_policy)
+        .map_err(CliError::invalid_config)?;
+    let registry = ToolRegistry::from_config(&config, &CancelToken::new())
+        .map_err(CliError::invalid_config)?;
+    let registered = registry.find(tool);
+    let read_only = registered.is_some_and(|t| t.read_only());
+    let verdict = policy.evaluate(tool, read_only, &input);
+
+    let mut output = format!("Tool: {}\nArguments: {}\n", tool, input);
+    output.push_str(&format!("Action: {}\n", verdict.action.as_str()));
+    match &verdict.rule {
+        Some(rule) => {
+            output.push_str(&format!("Rule: {}\n", rule));
+            if let Some(description) = policy.describe(rule) {
+                output.push_str(&format!("  {}\n", description));
+            }
+        }
+        None if read_only => output.push_str("Rule: none matched (read-only tools are allowed)\n"),
+        None => output.push_str(&format!(
+            "Rule: none matched (approval_policy = {})\n",
+            policy.fallback().as_str()
         )),
     }
+    if let Some(reason) = &verdict.reason {
+        output.push_str(&format!("Reason: {}\n", reason));
+    }
+    if registered.is_none() {
+        output.push_str(&format!("\nNote: no tool named '{}' is registered\n", tool));
+    }
+    let data = json!({
+        "tool": tool,
+        "arguments": input,
+        "action": verdict.action.as_str(),
+        "rule": verdict.rule,
+        "reason": verdict.reason,
+        "read_only": read_only,
+        "registered": registered.is_some(),
+    });
+    Ok(Reply::new(output.trim_end(), data))
 }
 
-fn cmd_tools(args: &[&str]) -> Result<String, String> {
+fn cmd_tools(ctx: &PluginContext, args: &[&str]) -> Result<Reply, CliError> {
     let subcommand = args.first().copied().unwrap_or("list");
 
     match subcommand {
         "list" => {
+            let registry = ToolRegistry::from_config(&load_config()?, &CancelToken::new())
+                .map_err(CliError::invalid_config)?
+                .with_services(tools::provider_tools(ctx));
+            for conflict in registry.conflicts() {
+                ctx.host().error(conflict);
+            }
+            let tools = registry.list();
+            let data = json!({ "tools": output::tool_list(&registry) });
+
             let mut output = String::from("Available tools:\n\n");
-            output.push_str("  (No tools registered - add tools via configuration)\n\n");
-            output.push_str("To add tools, edit ~/.config/adi/agent.toml:\n\n");
-            output.push_str("  [[tools]]\n");
-            output.push_str("  name = \"my_tool\"\n");
-            output.push_str("  command = \"my-command\"\n");
-            Ok(output.trim_end().to_string())
+            if tools.is_empty() {
+                output.push_str("  (No tools registered - add tools via configuration)\n\n");
+                output.push_str("To add tools, edit ~/.config/adi/agent.toml:\n\n");
+                output.push_str("  [[tools]]\n");
+                output.push_str("  name = \"my_tool\"\n");
+                output.push_str("  command = \"my-command\"\n");
+                return Ok(Reply::new(output.trim_end(), data));
+            }
+
+            let width = tools.iter().map(|(d, _)| d.name.len()).max().unwrap_or(0);
+            for (definition, source) in &tools {
+                output.push_str(&format!(
+                    "  {:<width$}  {}  ({})\n",
+                    definition.name,
+                    definition.description,
+                    source,
+                    width = width
+                ));
+            }
+            Ok(Reply::new(output.trim_end(), data))
+        }
+        _ => Err(format!("Unknown tools subcommand: {}. Use 'list'", subcommand).into()),
+    }
+}
+
+fn cmd_sessions(args: &[&str], options: &serde_json::Value) -> Result<Reply, CliError> {
+    let subcommand = args.first().copied().unwrap_or("list");
+    let store = SessionStore::open()?;
+
+    match subcommand {
+        "list" => {
+            let sessions = store.list()?;
+            let data = json!({
+                "sessions": sessions.iter().map(output::session_entry).collect::<Vec<_>>(),
+            });
+            if sessions.is_empty() {
+                return Ok(Reply::new("No saved sessions", data));
+            }
+
+            let mut output = String::from("Sessions:\n\n");
+            if options.get("tree").is_some() {
+                let known: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+                for root in sessions.iter().filter(|s| {
+                    s.parent
+                        .as_deref()
+                        .is_none_or(|parent| !known.contains(&parent))
+                }) {
+                    push_session_tree(&mut output, &sessions, root, 0);
+                }
+            } else {
+                for session in &sessions {
+                    output.push_str(&format!("  {}\n", session_line(session)));
+                }
+            }
+            Ok(Reply::new(output.trim_end(), data))
+        }
+        "show" => {
+            let Some(id) = args.get(1) else {
+                return Err(CliError::invalid_argument("Usage: sessions show <id>"));
+            };
+            let session = store.load(id).map_err(CliError::not_found)?;
+            let mut header = format!(
+                "Session: {}\nCreated: {}\nUpdated: {}\nModel: {} ({})\n",
+                session.id,
+                session::format_time(session.created_at),
+                session::format_time(session.updated_at),
+                session.model,
+                session.provider
+            );
+            if let (Some(parent), Some(turn)) = (&session.parent, session.forked_at) {
+                header.push_str(&format!("Forked from: {} at turn {}\n", parent, turn));
+            }
+            let mut data = output::session_entry(&session);
+            data["summary"] = output::run_summary(&session.summary);
+            data["turns"] = export::document_json(&session)["turns"].take();
+            Ok(Reply::new(
+                format!(
+                    "{}\n{}\n\nTranscript:\n\n{}",
+                    header,
+                    session.summary.render(),
+                    session.render_transcript()
+                ),
+                data,
+            ))
+        }
+        "fork" => {
+            const USAGE: &str = "Usage: sessions fork <id> --at <turn> [--message <text>]";
+            let Some(id) = args.get(1) else {
+                return Err(CliError::invalid_argument(USAGE));
+            };
+            let turn = options
+                .get("at")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| CliError::invalid_argument(USAGE))?
+                .parse::<usize>()
+                .map_err(|_| {
+                    CliError::invalid_argument(format!("--at expects a turn number. {}", USAGE))
+                })?;
+            let message = options.get("message").and_then(|v| v.as_str());
+
+            let parent = store.load(id).map_err(CliError::not_found)?;
+            let fork = parent
+                .fork(turn, message)
+                .map_err(CliError::invalid_argument)?;
+            store.save(&fork)?;
+            Ok(Reply::new(
+                format!(
+                    "Forked session {} from {} at turn {}\nContinue with: run --resume {}",
+                    fork.id, parent.id, turn, fork.id
+                ),
+                json!({"session": fork.id, "parent": parent.id, "turn": turn}),
+            ))
+        }
+        "cost" => {
+            let config = load_config()?;
+            let sessions = store.list()?;
+            let prices = PriceTable::new(&config.prices).map_err(CliError::invalid_config)?;
+            Ok(Reply::new(
+                cost::report(&sessions, &prices),
+                cost::report_json(&sessions, &prices),
+            ))
+        }
+        "export" => {
+            let Some(id) = args.get(1) else {
+                return Err(CliError::invalid_argument(
+                    "Usage: sessions export <id> [--format md|json|html]",
+                ));
+            };
+            let name = options
+                .get("format")
+                .and_then(|v| v.as_str())
+                .unwrap_or("md");
+            let format = export::Format::parse(name).map_err(CliError::invalid_argument)?;
+            let session = store.load(id).map_err(CliError::not_found)?;
+            let content = export::export(&session, format);
+            let data = json!({"session": session.id, "format": name, "content": content});
+            Ok(Reply::new(content, data))
         }
         _ => Err(format!(
-            "Unknown tools subcommand: {}. Use 'list'",
+            "Unknown sessions subcommand: {}. Use 'list', 'show', 'fork', 'export' or 'cost'",
             subcommand
-        )),
+        )
+        .into()),
+    }
+}
+
+/// The layered config; load errors are config errors.
+fn load_config() -> Result<Config, CliError> {
+    Config::load().map_err(CliError::invalid_config)
+}
+
+/// One line of `sessions list`.
+fn session_line(session: &Session) -> String {
+    let summary = &session.summary;
+    let mut task: String = summary.task.lines().next().unwrap_or("").to_string();
+    if task.chars().count() > 60 {
+        task = task.chars().take(57).collect::<String>() + "...";
+    }
+    format!(
+        "{}  {}  {:<16}  {:>3} iter  {}",
+        session.id,
+        session::format_time(session.updated_at),
+        summary.outcome.label(),
+        summary.iterations,
+        task
+    )
+}
+
+/// Append `session` and its forks, oldest fork first, indented by `depth`.
+fn push_session_tree(output: &mut String, sessions: &[Session], session: &Session, depth: usize) {
+    if depth == 0 {
+        output.push_str(&format!("  {}\n", session_line(session)));
+    } else {
+        output.push_str(&format!(
+            "  {}└─ turn {}: {}\n",
+            "   ".repeat(depth - 1),
+            session.forked_at.unwrap_or(0),
+            session_line(session)
+        ));
+    }
+
+    let mut children: Vec<&Session> = sessions
+        .iter()
+        .filter(|s| s.parent.as_deref() == Some(session.id.as_str()))
+        .collect();
+    children.sort_by_key(|s| (s.created_at, s.id.clone()));
+    for child in children {
+        push_session_tree(output, sessions, child, depth + 1);
     }
 }
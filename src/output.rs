@@ -0,0 +1,207 @@
+//! Text and JSON output of CLI commands.
+//!
+//! With `--output json` every command prints one JSON document:
+//!
+//! ```json
+//! {"schema_version": 1, "command": "sessions list", "ok": true, "data": {...}}
+//! {"schema_version": 1, "command": "run", "ok": false,
+//!  "error": {"code": "invalid_config", "message": "..."}}
+//! ```
+//!
+//! Fields may be added within a schema version; renaming or removing one,
+//! or changing its type, bumps `SCHEMA_VERSION`.
+
+use crate::agent::{RunOutcome, RunSummary};
+use crate::cli::ArgError;
+use crate::session::Session;
+use crate::tools::ToolRegistry;
+use serde_json::{json, Value};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// === Error Codes ===
+//
+// Rejected command lines use the codes of `ArgError::code`, such as
+// `unknown_option` or `missing_argument`.
+
+/// An option or argument has an invalid value.
+pub const INVALID_ARGUMENT: &str = "invalid_argument";
+/// A config file or setting is invalid.
+pub const INVALID_CONFIG: &str = "invalid_config";
+/// No single saved session matches the given id.
+pub const NOT_FOUND: &str = "not_found";
+/// Any other failure, e.g. an unreadable file.
+pub const FAILED: &str = "failed";
+
+/// Output format selected with `--output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown output format: {}. Use 'text' or 'json'",
+                value
+            )),
+        }
+    }
+}
+
+/// Result of a command in both output formats.
+pub struct Reply {
+    pub text: String,
+    pub data: Value,
+}
+
+impl Reply {
+    pub fn new(text: impl Into<String>, data: Value) -> Self {
+        Self {
+            text: text.into(),
+            data,
+        }
+    }
+}
+
+/// A failed command with a stable error code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(INVALID_ARGUMENT, message)
+    }
+
+    pub fn invalid_config(message: impl Into<String>) -> Self {
+        Self::new(INVALID_CONFIG, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(NOT_FOUND, message)
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        Self::new(FAILED, message)
+    }
+}
+
+impl From<ArgError> for CliError {
+    fn from(error: ArgError) -> Self {
+        Self::new(error.code(), error.to_string())
+    }
+}
+
+/// Render the result of `command` in `format`.
+///
+/// A failed command is still an `Err`, so callers keep a failing exit status
+/// in JSON mode.
+pub fn render(
+    format: OutputFormat,
+    command: &str,
+    result: Result<Reply, CliError>,
+) -> Result<String, String> {
+    match format {
+        OutputFormat::Text => result.map(|reply| reply.text).map_err(|e| e.message),
+        OutputFormat::Json => {
+            let ok = result.is_ok();
+            let document = match result {
+                Ok(reply) => json!({
+                    "schema_version": SCHEMA_VERSION,
+                    "command": command,
+                    "ok": true,
+                    "data": reply.data,
+                }),
+                Err(error) => json!({
+                    "schema_version": SCHEMA_VERSION,
+                    "command": command,
+                    "ok": false,
+                    "error": {"code": error.code, "message": error.message},
+                }),
+            };
+            let text = serde_json::to_string_pretty(&document).unwrap_or_default();
+            if ok {
+                Ok(text)
+            } else {
+                Err(text)
+            }
+        }
+    }
+}
+
+// === Data ===
+
+/// `run` result and the `summary` of `sessions show`.
+pub fn run_summary(summary: &RunSummary) -> Value {
+    let error = match &summary.outcome {
+        RunOutcome::Failed { error } => Some(error.as_str()),
+        _ => None,
+    };
+    json!({
+        "task": summary.task,
+        "outcome": summary.outcome.label(),
+        "error": error,
+        "iterations": summary.iterations,
+        "final_answer": summary.final_answer,
+        "usage": summary.usage,
+        "duration_ms": summary.duration_ms,
+        "tool_calls": summary.tool_calls,
+        "budget": summary.budget.map(|budget| json!({
+            "limit": budget.limit,
+            "used": budget.used,
+            "remaining": budget.remaining(),
+        })),
+        "cost_usd": summary.cost_usd,
+        "cost_cap": summary.cost_cap,
+    })
+}
+
+/// A session in `sessions list`.
+pub fn session_entry(session: &Session) -> Value {
+    json!({
+        "id": session.id,
+        "parent": session.parent,
+        "forked_at": session.forked_at,
+        "created_at": session.created_at,
+        "updated_at": session.updated_at,
+        "provider": session.provider,
+        "model": session.model,
+        "task": session.summary.task,
+        "outcome": session.summary.outcome.label(),
+        "iterations": session.summary.iterations,
+        "cost_usd": session.summary.cost_usd,
+    })
+}
+
+/// The tools of `tools list`.
+pub fn tool_list(registry: &ToolRegistry) -> Value {
+    registry
+        .list()
+        .iter()
+        .map(|(definition, source)| {
+            json!({
+                "name": definition.name,
+                "description": definition.description,
+                "parameters": definition.parameters,
+                "source": source,
+                "read_only": registry.find(&definition.name).is_some_and(|t| t.read_only()),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,407 @@
+//! Anthropic Messages API backend.
+
+use super::{
+    append, call_cancellable, call_streaming, http_agent, max_output_tokens, read_events, Provider,
+    ProviderResponse,
+};
+use crate::cancel::CancelToken;
+use crate::config::Settings;
+use crate::message::{ContentBlock, Message, ToolDefinition, Usage};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const API_VERSION: &str = "2023-06-01";
+const API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+
+pub struct AnthropicProvider {
+    agent: ureq::Agent,
+    /// Limit on a whole non-streamed call.
+    timeout: Duration,
+    endpoint: String,
+    api_key: Option<String>,
+    model: String,
+    max_tokens: u64,
+    cancel: CancelToken,
+}
+
+impl AnthropicProvider {
+    pub fn new(settings: &Settings, cancel: &CancelToken) -> Result<Self, String> {
+        let api_key = std::env::var(API_KEY_ENV).ok().filter(|k| !k.is_empty());
+        let base_url = settings
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/');
+        if api_key.is_none() && base_url == DEFAULT_BASE_URL {
+            return Err(format!("{} is not set", API_KEY_ENV));
+        }
+
+        Ok(Self {
+            agent: http_agent(settings),
+            timeout: Duration::from_millis(settings.model_timeout_ms),
+            endpoint: format!("{}/v1/messages", base_url),
+            api_key,
+            model: settings.model.clone(),
+            max_tokens: max_output_tokens(settings),
+            cancel: cancel.clone(),
+        })
+    }
+
+    fn request_body(&self, system: &str, messages: &[Message], tools: &[ToolDefinition]) -> Value {
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "system": system,
+            "messages": messages,
+        });
+        if !tools.is_empty() {
+            body["tools"] = tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters,
+                    })
+                })
+                .collect();
+        }
+        body
+    }
+
+    fn request(&self) -> ureq::Request {
+        let mut request = self
+            .agent
+            .post(&self.endpoint)
+            .set("anthropic-version", API_VERSION)
+            .set("content-type", "application/json");
+        if let Some(key) = &self.api_key {
+            request = request.set("x-api-key", key);
+        }
+        request
+    }
+}
+
+impl Provider for AnthropicProvider {
+    fn complete(
+        &mut self,
+        system: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ProviderResponse, String> {
+        let request = self.request().timeout(self.timeout);
+        let payload = self.request_body(system, messages, tools);
+        let body = call_cancellable(&self.cancel, move || {
+            send(request, payload)?
+                .into_json::<Value>()
+                .map_err(|e| format!("Invalid Anthropic API response: {}", e))
+        })?;
+        parse_response(&body)
+    }
+
+    fn complete_streaming(
+        &mut self,
+        system: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<ProviderResponse, String> {
+        let request = self.request();
+        let mut payload = self.request_body(system, messages, tools);
+        payload["stream"] = json!(true);
+        let body = call_streaming(&self.cancel, on_text, move |text| {
+            let mut body = json!({});
+            read_events(send(request, payload)?, |_, data| {
+                let event: Value = serde_json::from_str(data)
+                    .map_err(|e| format!("Invalid Anthropic API stream event: {}", e))?;
+                apply_event(&mut body, &event, text)
+            })?;
+            Ok(body)
+        })?;
+        parse_response(&body)
+    }
+}
+
+fn send(request: ureq::Request, payload: Value) -> Result<ureq::Response, String> {
+    match request.send_json(payload) {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(code, response)) => {
+            let body: Value = response.into_json().unwrap_or(Value::Null);
+            let message = body["error"]["message"]
+                .as_str()
+                .unwrap_or("no error message");
+            Err(format!("Anthropic API error ({}): {}", code, message))
+        }
+        Err(e) => Err(format!("Anthropic API request failed: {}", e)),
+    }
+}
+
+/// Fold one stream event into `body`, which ends up shaped like a
+/// non-streamed response.
+fn apply_event(body: &mut Value, event: &Value, text: &dyn Fn(String)) -> Result<(), String> {
+    match event["type"].as_str() {
+        Some("message_start") => *body = event["message"].clone(),
+        Some("content_block_start") => {
+            if let Some(content) = body["content"].as_array_mut() {
+                content.push(event["content_block"].clone());
+            }
+        }
+        Some("content_block_delta") => {
+            let index = event["index"].as_u64().unwrap_or(0) as usize;
+            let Some(block) = body["content"].get_mut(index) else {
+                return Ok(());
+            };
+            let delta = &event["delta"];
+            match delta["type"].as_str() {
+                Some("text_delta") => {
+                    let delta = delta["text"].as_str().unwrap_or_default();
+                    append(&mut block["text"], delta);
+                    text(delta.to_string());
+                }
+                Some("input_json_delta") => append(
+                    &mut block["partial_json"],
+                    delta["partial_json"].as_str().unwrap_or_default(),
+                ),
+                _ => {}
+            }
+        }
+        Some("content_block_stop") => {
+            let index = event["index"].as_u64().unwrap_or(0) as usize;
+            let Some(block) = body["content"]
+                .get_mut(index)
+                .and_then(Value::as_object_mut)
+            else {
+                return Ok(());
+            };
+            // Tool input arrives as pieces of JSON text.
+            if let Some(Value::String(json)) = block.remove("partial_json") {
+                if !json.is_empty() {
+                    let input = serde_json::from_str(&json).map_err(|e| {
+                        format!("Invalid tool input in Anthropic API stream: {}", e)
+                    })?;
+                    block.insert("input".to_string(), input);
+                }
+            }
+        }
+        Some("message_delta") => {
+            if let Some(usage) = event["usage"].as_object() {
+                for (key, value) in usage {
+                    body["usage"][key] = value.clone();
+                }
+            }
+        }
+        Some("error") => {
+            return Err(format!(
+                "Anthropic API error: {}",
+                event["error"]["message"]
+                    .as_str()
+                    .unwrap_or("no error message")
+            ))
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_response(body: &Value) -> Result<ProviderResponse, String> {
+    let blocks = body["content"]
+        .as_array()
+        .ok_or("Invalid Anthropic API response: missing content")?;
+
+    let mut content = Vec::new();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("text") => content.push(ContentBlock::Text {
+                text: block["text"].as_str().unwrap_or_default().to_string(),
+            }),
+            Some("tool_use") => content.push(ContentBlock::ToolUse {
+                id: block["id"].as_str().unwrap_or_default().to_string(),
+                name: block["name"].as_str().unwrap_or_default().to_string(),
+                input: block["input"].clone(),
+            }),
+            // Thinking and other block types are not replayed to the model.
+            _ => {}
+        }
+    }
+
+    let usage = body
+        .get("usage")
+        .filter(|u| u.is_object())
+        .map(|usage| Usage {
+            input_tokens: usage["input_tokens"].as_u64().unwrap_or(0),
+            output_tokens: usage["output_tokens"].as_u64().unwrap_or(0),
+            cache_read_tokens: usage["cache_read_input_tokens"].as_u64().unwrap_or(0),
+            cache_write_tokens: usage["cache_creation_input_tokens"].as_u64().unwrap_or(0),
+        });
+
+    Ok(ProviderResponse { content, usage })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::tests::serve;
+
+    fn provider(base_url: String, model_timeout_ms: u64) -> AnthropicProvider {
+        let settings = Settings {
+            base_url: Some(base_url),
+            model_timeout_ms,
+            ..Settings::default()
+        };
+        AnthropicProvider::new(&settings, &CancelToken::new()).unwrap()
+    }
+
+    fn sse(event: Value) -> String {
+        format!(
+            "event: {}\ndata: {}\n\n",
+            event["type"].as_str().unwrap(),
+            event
+        )
+    }
+
+    #[test]
+    fn streamed_reply_may_outlast_the_model_timeout() {
+        let step = Duration::from_millis(400);
+        let mut chunks = vec![(
+            Duration::ZERO,
+            sse(
+                json!({"type": "message_start", "message": {"content": [], "usage": {"input_tokens": 3}}}),
+            ),
+        )];
+        chunks.push((
+            Duration::ZERO,
+            sse(json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}})),
+        ));
+        for word in ["slow ", "but ", "steady"] {
+            chunks.push((
+                step,
+                sse(json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": word}})),
+            ));
+        }
+        chunks.push((
+            Duration::ZERO,
+            sse(json!({"type": "message_delta", "usage": {"output_tokens": 4}})),
+        ));
+        let (url, _) = serve(200, chunks);
+
+        let mut streamed = String::new();
+        let response = provider(url, 1_000)
+            .complete_streaming("system", &[], &[], &mut |text| streamed.push_str(text))
+            .unwrap();
+        assert_eq!(streamed, "slow but steady");
+        assert_eq!(response.usage.unwrap().output_tokens, 4);
+    }
+
+    fn read_file_tool() -> ToolDefinition {
+        ToolDefinition {
+            name: "read_file".to_string(),
+            description: "Read a file".to_string(),
+            parameters: json!({"type": "object", "properties": {"path": {"type": "string"}}}),
+        }
+    }
+
+    #[test]
+    fn request_and_reply_map_to_the_messages_api() {
+        let reply = json!({
+            "content": [
+                {"type": "thinking", "thinking": "hidden"},
+                {"type": "text", "text": "Reading it."},
+                {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {"path": "a.txt"}},
+            ],
+            "usage": {
+                "input_tokens": 10,
+                "output_tokens": 5,
+                "cache_read_input_tokens": 3,
+                "cache_creation_input_tokens": 2,
+            },
+        });
+        let (url, request) = serve(200, vec![(Duration::ZERO, reply.to_string())]);
+
+        let response = provider(url, 10_000)
+            .complete(
+                "Be brief.",
+                &[Message::user("Read a.txt")],
+                &[read_file_tool()],
+            )
+            .unwrap();
+
+        let sent: Value = serde_json::from_str(&request.recv().unwrap()).unwrap();
+        assert_eq!(sent["model"], Settings::default().model);
+        assert_eq!(sent["system"], "Be brief.");
+        assert_eq!(
+            sent["messages"],
+            json!([{"role": "user", "content": [{"type": "text", "text": "Read a.txt"}]}])
+        );
+        assert_eq!(sent["tools"][0]["name"], "read_file");
+        assert_eq!(sent["tools"][0]["input_schema"]["type"], "object");
+        assert!(sent.get("stream").is_none());
+
+        assert_eq!(
+            response.content,
+            vec![
+                ContentBlock::Text {
+                    text: "Reading it.".to_string()
+                },
+                ContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!({"path": "a.txt"}),
+                },
+            ]
+        );
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                input_tokens: 10,
+                output_tokens: 5,
+                cache_read_tokens: 3,
+                cache_write_tokens: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn api_errors_carry_the_status_and_message() {
+        let body = json!({"type": "error", "error": {"type": "invalid_request_error", "message": "max_tokens is too large"}});
+        let (url, _) = serve(400, vec![(Duration::ZERO, body.to_string())]);
+        let err = provider(url, 10_000)
+            .complete("system", &[Message::user("hi")], &[])
+            .unwrap_err();
+        assert_eq!(err, "Anthropic API error (400): max_tokens is too large");
+    }
+
+    #[test]
+    fn streamed_tool_input_is_assembled_from_pieces() {
+        let mut body = json!({});
+        let events = [
+            json!({"type": "message_start", "message": {"content": [], "usage": {"input_tokens": 8}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "tool_use", "id": "toolu_2", "name": "read_file", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": "{\"path\": "}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": "\"b.txt\"}"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 12}}),
+        ];
+        for event in &events {
+            apply_event(&mut body, event, &|_| {}).unwrap();
+        }
+
+        let response = parse_response(&body).unwrap();
+        assert_eq!(
+            response.content,
+            vec![ContentBlock::ToolUse {
+                id: "toolu_2".to_string(),
+                name: "read_file".to_string(),
+                input: json!({"path": "b.txt"}),
+            }]
+        );
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (8, 12));
+
+        let error = json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}});
+        assert_eq!(
+            apply_event(&mut body, &error, &|_| {}).unwrap_err(),
+            "Anthropic API error: Overloaded"
+        );
+    }
+}
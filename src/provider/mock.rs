@@ -0,0 +1,206 @@
+//! Scripted provider for offline runs.
+//!
+//! Replays assistant turns from a JSON or TOML script in order:
+//!
+//! ```toml
+//! [[turns]]
+//! text = "Listing files first."
+//!
+//! [[turns.tool_calls]]
+//! name = "list_dir"
+//! input = { path = "." }
+//!
+//! [[turns]]
+//! text = "Done."
+//! ```
+
+use super::{Provider, ProviderResponse};
+use crate::message::{ContentBlock, Message, ToolDefinition, Usage};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct Script {
+    turns: Vec<ScriptTurn>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptTurn {
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ScriptToolCall>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptToolCall {
+    #[serde(default)]
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    input: Value,
+}
+
+pub struct MockProvider {
+    path: String,
+    turns: std::vec::IntoIter<ScriptTurn>,
+    consumed: usize,
+}
+
+impl MockProvider {
+    /// Load a script; `.toml` files are parsed as TOML, anything else as JSON.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read mock script {}: {}", path, e))?;
+
+        let is_toml = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        let script: Script = if is_toml {
+            toml::from_str(&content).map_err(|e| format!("Invalid mock script {}: {}", path, e))?
+        } else {
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid mock script {}: {}", path, e))?
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            turns: script.turns.into_iter(),
+            consumed: 0,
+        })
+    }
+}
+
+impl Provider for MockProvider {
+    fn complete(
+        &mut self,
+        _system: &str,
+        _messages: &[Message],
+        _tools: &[ToolDefinition],
+    ) -> Result<ProviderResponse, String> {
+        let turn = self.turns.next().ok_or_else(|| {
+            format!(
+                "Mock script {} exhausted after {} turn(s)",
+                self.path, self.consumed
+            )
+        })?;
+        self.consumed += 1;
+
+        let mut content = Vec::new();
+        if let Some(text) = turn.text {
+            content.push(ContentBlock::Text { text });
+        }
+        for (index, call) in turn.tool_calls.into_iter().enumerate() {
+            content.push(ContentBlock::ToolUse {
+                id: call
+                    .id
+                    .unwrap_or_else(|| format!("mock_{}_{}", self.consumed, index)),
+                name: call.name,
+                input: if call.input.is_null() {
+                    Value::Object(Default::default())
+                } else {
+                    call.input
+                },
+            });
+        }
+
+        Ok(ProviderResponse {
+            content,
+            usage: turn.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn script(name: &str, content: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("adi-agent-mock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn turns_are_replayed_in_order_until_the_script_runs_out() {
+        let path = script(
+            "turns.toml",
+            r#"
+[[turns]]
+text = "Listing files first."
+
+[[turns.tool_calls]]
+name = "list_dir"
+input = { path = "." }
+
+[[turns.tool_calls]]
+id = "fixed"
+name = "glob"
+
+[[turns]]
+text = "Done."
+usage = { input_tokens = 12, output_tokens = 3 }
+"#,
+        );
+        let mut provider = MockProvider::from_file(&path).unwrap();
+
+        let first = provider.complete("", &[], &[]).unwrap();
+        assert_eq!(
+            first.content,
+            vec![
+                ContentBlock::Text {
+                    text: "Listing files first.".to_string()
+                },
+                ContentBlock::ToolUse {
+                    id: "mock_1_0".to_string(),
+                    name: "list_dir".to_string(),
+                    input: serde_json::json!({"path": "."}),
+                },
+                ContentBlock::ToolUse {
+                    id: "fixed".to_string(),
+                    name: "glob".to_string(),
+                    input: serde_json::json!({}),
+                },
+            ]
+        );
+        assert_eq!(first.usage, None);
+
+        let second = provider.complete("", &[], &[]).unwrap();
+        assert_eq!(second.usage.unwrap().input_tokens, 12);
+
+        let err = provider.complete("", &[], &[]).unwrap_err();
+        assert_eq!(
+            err,
+            format!("Mock script {} exhausted after 2 turn(s)", path)
+        );
+    }
+
+    #[test]
+    fn json_scripts_and_errors() {
+        let path = script("turns.json", r#"{"turns": [{"text": "Hi"}]}"#);
+        let mut provider = MockProvider::from_file(&path).unwrap();
+        assert_eq!(
+            provider.complete("", &[], &[]).unwrap().content,
+            vec![ContentBlock::Text {
+                text: "Hi".to_string()
+            }]
+        );
+
+        let bad = script("bad.json", r#"{"turns": [{"tool_calls": [{}]}]}"#);
+        let err = MockProvider::from_file(&bad).err().unwrap();
+        assert!(
+            err.starts_with(&format!("Invalid mock script {}", bad)),
+            "{}",
+            err
+        );
+        let missing = format!("{}.missing", bad);
+        let err = MockProvider::from_file(&missing).err().unwrap();
+        assert!(err.starts_with("Failed to read mock script"), "{}", err);
+    }
+}
@@ -0,0 +1,289 @@
+//! Interface between the agent loop and an LLM backend.
+
+mod anthropic;
+mod mock;
+mod openai;
+
+use crate::cancel::CancelToken;
+use crate::config::{ProviderKind, Settings};
+use crate::message::{ContentBlock, Message, ToolDefinition, Usage};
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+pub use anthropic::AnthropicProvider;
+pub use mock::MockProvider;
+pub use openai::OpenAiProvider;
+
+/// Upper bound on output tokens requested per call.
+///
+/// `max_tokens` in the settings is the budget for the whole run, which is
+/// larger than any model accepts for a single response.
+const MAX_OUTPUT_TOKENS: u64 = 8192;
+
+/// How often a model call in flight checks for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Output tokens requested from the model per call.
+pub fn max_output_tokens(settings: &Settings) -> u64 {
+    settings.max_tokens.min(MAX_OUTPUT_TOKENS)
+}
+
+/// One assistant turn returned by a provider.
+#[derive(Debug, Clone)]
+pub struct ProviderResponse {
+    pub content: Vec<ContentBlock>,
+    /// `None` when the backend did not report usage.
+    pub usage: Option<Usage>,
+}
+
+/// A model backend the agent loop can call.
+pub trait Provider {
+    /// Produce the next assistant turn for the conversation.
+    fn complete(
+        &mut self,
+        system: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ProviderResponse, String>;
+
+    /// Like [`complete`](Provider::complete), passing assistant text to
+    /// `on_text` as the model generates it.
+    ///
+    /// Backends that cannot stream pass each text block once the turn is
+    /// complete.
+    fn complete_streaming(
+        &mut self,
+        system: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<ProviderResponse, String> {
+        let response = self.complete(system, messages, tools)?;
+        for block in &response.content {
+            if let ContentBlock::Text { text } = block {
+                on_text(text);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Build the provider selected by `settings`; model calls are abandoned once `cancel` fires.
+pub fn from_settings(
+    settings: &Settings,
+    cancel: &CancelToken,
+) -> Result<Box<dyn Provider>, String> {
+    match settings.provider {
+        ProviderKind::Anthropic => Ok(Box::new(AnthropicProvider::new(settings, cancel)?)),
+        ProviderKind::OpenAiCompatible => Ok(Box::new(OpenAiProvider::new(settings, cancel)?)),
+        ProviderKind::Mock => {
+            let path = settings
+                .mock_script
+                .as_deref()
+                .ok_or("The mock provider requires a script (--mock-script <path>)")?;
+            Ok(Box::new(MockProvider::from_file(path)?))
+        }
+    }
+}
+
+/// HTTP agent for model calls.
+///
+/// It limits each connect, read and write rather than the whole exchange,
+/// so a long streamed reply is not cut off; `complete` sets a limit on the
+/// whole call itself.
+fn http_agent(settings: &Settings) -> ureq::Agent {
+    let timeout = Duration::from_millis(settings.model_timeout_ms);
+    ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .timeout_write(timeout)
+        .build()
+}
+
+/// Rough usage of a call for backends that do not report it, at about four
+/// characters per token of the serialized request and reply.
+pub fn estimate_usage(
+    system: &str,
+    messages: &[Message],
+    tools: &[ToolDefinition],
+    content: &[ContentBlock],
+) -> Usage {
+    let request = system.len()
+        + serde_json::to_string(messages).map_or(0, |s| s.len())
+        + serde_json::to_string(tools).map_or(0, |s| s.len());
+    let reply = serde_json::to_string(content).map_or(0, |s| s.len());
+    Usage {
+        input_tokens: request.div_ceil(4) as u64,
+        output_tokens: reply.div_ceil(4) as u64,
+        ..Default::default()
+    }
+}
+
+/// Run a blocking HTTP exchange on a helper thread so `cancel` can abandon it.
+///
+/// ureq cannot interrupt a request in flight; an abandoned exchange finishes
+/// on its own within `model_timeout_ms` and its result is dropped.
+fn call_cancellable<T: Send + 'static>(
+    cancel: &CancelToken,
+    exchange: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    call_streaming(cancel, &mut |_| {}, move |_| exchange())
+}
+
+/// Message from an exchange running on a helper thread.
+enum Progress<T> {
+    Text(String),
+    Done(Result<T, String>),
+}
+
+/// [`call_cancellable`] for a streamed exchange, which passes text to the
+/// function it is given as the text arrives; the text reaches `on_text` on
+/// the calling thread.
+fn call_streaming<T: Send + 'static>(
+    cancel: &CancelToken,
+    on_text: &mut dyn FnMut(&str),
+    exchange: impl FnOnce(&dyn Fn(String)) -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    let (sender, progress) = mpsc::channel();
+    thread::spawn(move || {
+        let text = |delta: String| {
+            let _ = sender.send(Progress::Text(delta));
+        };
+        let result = exchange(&text);
+        let _ = sender.send(Progress::Done(result));
+    });
+    loop {
+        if cancel.is_cancelled() {
+            return Err("Model call cancelled".to_string());
+        }
+        match progress.recv_timeout(POLL_INTERVAL) {
+            Ok(Progress::Text(delta)) => on_text(&delta),
+            Ok(Progress::Done(result)) => return result,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err("Model call ended without a response".to_string())
+            }
+        }
+    }
+}
+
+/// Pass each server-sent event of `response` to `on_event` as its event
+/// name (empty when unnamed) and data.
+fn read_events(
+    response: ureq::Response,
+    mut on_event: impl FnMut(&str, &str) -> Result<(), String>,
+) -> Result<(), String> {
+    let reader = BufReader::new(response.into_reader());
+    let mut event = String::new();
+    let mut data = String::new();
+    for line in reader.lines() {
+        let line = line.map_err(|e| format!("Failed to read streamed response: {}", e))?;
+        if line.is_empty() {
+            if !data.is_empty() {
+                on_event(&event, &data)?;
+            }
+            event.clear();
+            data.clear();
+        } else if let Some(name) = line.strip_prefix("event:") {
+            event = name.trim().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    if !data.is_empty() {
+        on_event(&event, &data)?;
+    }
+    Ok(())
+}
+
+/// Append `delta` to the string at `slot`, which may be unset.
+fn append(slot: &mut serde_json::Value, delta: &str) {
+    let mut text = slot.as_str().unwrap_or_default().to_string();
+    text.push_str(delta);
+    *slot = serde_json::Value::String(text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serve one HTTP request on a local port, answering with `status` and
+    /// `chunks` of body, each written after its delay. Returns the base URL
+    /// and the request that arrived.
+    pub(super) fn serve(
+        status: u16,
+        chunks: Vec<(Duration, String)>,
+    ) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, request) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buffer).unwrap();
+                received.extend_from_slice(&buffer[..n]);
+                let text = String::from_utf8_lossy(&received).into_owned();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())?
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        let _ = sender.send(body.to_string());
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            let head = format!(
+                "HTTP/1.1 {} X\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n",
+                status
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            for (delay, chunk) in chunks {
+                thread::sleep(delay);
+                if stream.write_all(chunk.as_bytes()).is_err() {
+                    return;
+                }
+                let _ = stream.flush();
+            }
+        });
+        (url, request)
+    }
+
+    #[test]
+    fn server_sent_events_are_split_on_blank_lines() {
+        let body = "event: a\ndata: 1\n\n: comment\ndata: 2\ndata:3\n\ndata: last";
+        let (url, _) = serve(200, vec![(Duration::ZERO, body.to_string())]);
+        let response = ureq::get(&url).call().unwrap();
+        let mut events = Vec::new();
+        read_events(response, |name, data| {
+            events.push((name.to_string(), data.to_string()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                ("a".to_string(), "1".to_string()),
+                (String::new(), "2\n3".to_string()),
+                (String::new(), "last".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,490 @@
+//! OpenAI-compatible chat-completions backend.
+//!
+//! Works with OpenAI and local servers (llama.cpp, vLLM, Ollama) that expose
+//! `/v1/chat/completions` with function calling.
+
+use super::{
+    append, call_cancellable, call_streaming, http_agent, max_output_tokens, read_events, Provider,
+    ProviderResponse,
+};
+use crate::cancel::CancelToken;
+use crate::config::Settings;
+use crate::message::{ContentBlock, Message, Role, ToolDefinition, Usage};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const API_KEY_ENV: &str = "OPENAI_API_KEY";
+const DEFAULT_BASE_URL: &str = "https://api.openai.com";
+
+pub struct OpenAiProvider {
+    agent: ureq::Agent,
+    /// Limit on a whole non-streamed call.
+    timeout: Duration,
+    endpoint: String,
+    api_key: Option<String>,
+    model: String,
+    max_tokens: u64,
+    cancel: CancelToken,
+}
+
+impl OpenAiProvider {
+    pub fn new(settings: &Settings, cancel: &CancelToken) -> Result<Self, String> {
+        let api_key = std::env::var(API_KEY_ENV).ok().filter(|k| !k.is_empty());
+        let base_url = settings
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/');
+        if api_key.is_none() && base_url == DEFAULT_BASE_URL {
+            return Err(format!("{} is not set", API_KEY_ENV));
+        }
+
+        // Accept both `http://host:port` and `http://host:port/v1`.
+        let endpoint = if base_url.ends_with("/v1") {
+            format!("{}/chat/completions", base_url)
+        } else {
+            format!("{}/v1/chat/completions", base_url)
+        };
+
+        Ok(Self {
+            agent: http_agent(settings),
+            timeout: Duration::from_millis(settings.model_timeout_ms),
+            endpoint,
+            api_key,
+            model: settings.model.clone(),
+            max_tokens: max_output_tokens(settings),
+            cancel: cancel.clone(),
+        })
+    }
+
+    fn request_body(&self, system: &str, messages: &[Message], tools: &[ToolDefinition]) -> Value {
+        let mut wire = vec![json!({"role": "system", "content": system})];
+        for message in messages {
+            wire.extend(to_wire(message));
+        }
+
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "messages": wire,
+        });
+        if !tools.is_empty() {
+            body["tools"] = tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        }
+                    })
+                })
+                .collect();
+        }
+        body
+    }
+
+    fn request(&self) -> ureq::Request {
+        let mut request = self
+            .agent
+            .post(&self.endpoint)
+            .set("content-type", "application/json");
+        if let Some(key) = &self.api_key {
+            request = request.set("authorization", &format!("Bearer {}", key));
+        }
+        request
+    }
+}
+
+impl Provider for OpenAiProvider {
+    fn complete(
+        &mut self,
+        system: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<ProviderResponse, String> {
+        let request = self.request().timeout(self.timeout);
+        let payload = self.request_body(system, messages, tools);
+        let body = call_cancellable(&self.cancel, move || {
+            send(request, payload)?
+                .into_json::<Value>()
+                .map_err(|e| format!("Invalid chat completions response: {}", e))
+        })?;
+        parse_response(&body)
+    }
+
+    fn complete_streaming(
+        &mut self,
+        system: &str,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        on_text: &mut dyn FnMut(&str),
+    ) -> Result<ProviderResponse, String> {
+        let request = self.request();
+        let mut payload = self.request_body(system, messages, tools);
+        payload["stream"] = json!(true);
+        payload["stream_options"] = json!({"include_usage": true});
+        let body = call_streaming(&self.cancel, on_text, move |text| {
+            let mut message = json!({"content": "", "tool_calls": []});
+            let mut usage = Value::Null;
+            read_events(send(request, payload)?, |_, data| {
+                if data == "[DONE]" {
+                    return Ok(());
+                }
+                let chunk: Value = serde_json::from_str(data)
+                    .map_err(|e| format!("Invalid chat completions stream chunk: {}", e))?;
+                if let Some(error) = chunk.get("error") {
+                    return Err(format!(
+                        "Chat completions API error: {}",
+                        error["message"].as_str().unwrap_or("no error message")
+                    ));
+                }
+                if chunk["usage"].is_object() {
+                    usage = chunk["usage"].clone();
+                }
+                apply_delta(&mut message, &chunk["choices"][0]["delta"], text);
+                Ok(())
+            })?;
+            Ok(json!({"choices": [{"message": message}], "usage": usage}))
+        })?;
+        parse_response(&body)
+    }
+}
+
+fn send(request: ureq::Request, payload: Value) -> Result<ureq::Response, String> {
+    match request.send_json(payload) {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(code, response)) => {
+            let body: Value = response.into_json().unwrap_or(Value::Null);
+            let message = body["error"]["message"]
+                .as_str()
+                .unwrap_or("no error message");
+            Err(format!(
+                "Chat completions API error ({}): {}",
+                code, message
+            ))
+        }
+        Err(e) => Err(format!("Chat completions request failed: {}", e)),
+    }
+}
+
+/// Fold one streamed `delta` into `message`, which ends up shaped like the
+/// message of a non-streamed response.
+fn apply_delta(message: &mut Value, delta: &Value, text: &dyn Fn(String)) {
+    if let Some(content) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+        append(&mut message["content"], content);
+        text(content.to_string());
+    }
+    let (Some(deltas), Some(calls)) = (
+        delta["tool_calls"].as_array(),
+        message["tool_calls"].as_array_mut(),
+    ) else {
+        return;
+    };
+    // Each call arrives in pieces keyed by its index: the id and name
+    // first, then the arguments as fragments of JSON text.
+    for piece in deltas {
+        let index = piece["index"].as_u64().unwrap_or(0) as usize;
+        while calls.len() <= index {
+            calls.push(json!({"type": "function", "function": {"name": "", "arguments": ""}}));
+        }
+        let call = &mut calls[index];
+        if let Some(id) = piece["id"].as_str() {
+            call["id"] = json!(id);
+        }
+        if let Some(name) = piece["function"]["name"].as_str() {
+            append(&mut call["function"]["name"], name);
+        }
+        if let Some(arguments) = piece["function"]["arguments"].as_str() {
+            append(&mut call["function"]["arguments"], arguments);
+        }
+    }
+}
+
+/// Convert one transcript message into chat-completions messages.
+///
+/// Tool results become separate `tool` role messages.
+fn to_wire(message: &Message) -> Vec<Value> {
+    match message.role {
+        Role::Assistant => {
+            let tool_calls: Vec<Value> = message
+                .tool_calls()
+                .into_iter()
+                .map(|call| {
+                    json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": {
+                            "name": call.name,
+                            "arguments": call.input.to_string(),
+                        }
+                    })
+                })
+                .collect();
+            let text = message.text();
+            let mut wire = json!({
+                "role": "assistant",
+                "content": if text.is_empty() { Value::Null } else { Value::String(text) },
+            });
+            if !tool_calls.is_empty() {
+                wire["tool_calls"] = Value::Array(tool_calls);
+            }
+            vec![wire]
+        }
+        Role::User => {
+            let mut wire = Vec::new();
+            for block in &message.content {
+                match block {
+                    ContentBlock::Text { text } => {
+                        wire.push(json!({"role": "user", "content": text}));
+                    }
+                    ContentBlock::ToolResult {
+                        tool_use_id,
+                        content,
+                        ..
+                    } => {
+                        wire.push(json!({
+                            "role": "tool",
+                            "tool_call_id": tool_use_id,
+                            "content": content,
+                        }));
+                    }
+                    ContentBlock::ToolUse { .. } => {}
+                }
+            }
+            wire
+        }
+    }
+}
+
+fn parse_response(body: &Value) -> Result<ProviderResponse, String> {
+    let message = &body["choices"][0]["message"];
+    if !message.is_object() {
+        return Err("Invalid chat completions response: missing choices".to_string());
+    }
+
+    let mut content = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        content.push(ContentBlock::Text {
+            text: text.to_string(),
+        });
+    }
+
+    if let Some(calls) = message["tool_calls"].as_array() {
+        for (index, call) in calls.iter().enumerate() {
+            // Some local servers omit ids; the model only needs them to be unique.
+            let id = call["id"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| format!("call_{}", index));
+            let arguments = &call["function"]["arguments"];
+            // Arguments arrive as a JSON string; keep malformed ones verbatim so
+            // the tool can report the problem back to the model.
+            let input = match arguments.as_str() {
+                Some(raw) => serde_json::from_str(raw).unwrap_or_else(|_| json!(raw)),
+                None => arguments.clone(),
+            };
+            content.push(ContentBlock::ToolUse {
+                id,
+                name: call["function"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                input,
+            });
+        }
+    }
+
+    // Local servers often leave usage out; the loop estimates it then.
+    let usage = body.get("usage").filter(|u| u.is_object()).map(|usage| {
+        // `prompt_tokens` includes the cached part.
+        let cached = usage["prompt_tokens_details"]["cached_tokens"]
+            .as_u64()
+            .unwrap_or(0);
+        Usage {
+            input_tokens: usage["prompt_tokens"]
+                .as_u64()
+                .unwrap_or(0)
+                .saturating_sub(cached),
+            output_tokens: usage["completion_tokens"].as_u64().unwrap_or(0),
+            cache_read_tokens: cached,
+            cache_write_tokens: 0,
+        }
+    });
+
+    Ok(ProviderResponse { content, usage })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::tests::serve;
+
+    fn provider(base_url: &str) -> OpenAiProvider {
+        let settings = Settings {
+            base_url: Some(base_url.to_string()),
+            model: "local-model".to_string(),
+            ..Settings::default()
+        };
+        OpenAiProvider::new(&settings, &CancelToken::new()).unwrap()
+    }
+
+    #[test]
+    fn transcript_maps_to_chat_messages() {
+        let provider = provider("http://localhost:8080/v1/");
+        assert_eq!(
+            provider.endpoint,
+            "http://localhost:8080/v1/chat/completions"
+        );
+
+        let messages = [
+            Message::user("List the files"),
+            Message::assistant(vec![
+                ContentBlock::Text {
+                    text: "Listing.".to_string(),
+                },
+                ContentBlock::ToolUse {
+                    id: "call_a".to_string(),
+                    name: "list_dir".to_string(),
+                    input: json!({"path": "."}),
+                },
+            ]),
+            Message {
+                role: Role::User,
+                content: vec![
+                    ContentBlock::ToolResult {
+                        tool_use_id: "call_a".to_string(),
+                        content: "a.txt".to_string(),
+                        is_error: false,
+                    },
+                    ContentBlock::Text {
+                        text: "Only text files.".to_string(),
+                    },
+                ],
+            },
+        ];
+        let tools = [ToolDefinition {
+            name: "list_dir".to_string(),
+            description: "List a directory".to_string(),
+            parameters: json!({"type": "object"}),
+        }];
+        let body = provider.request_body("Be brief.", &messages, &tools);
+
+        assert_eq!(body["model"], "local-model");
+        assert_eq!(
+            body["messages"],
+            json!([
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "List the files"},
+                {
+                    "role": "assistant",
+                    "content": "Listing.",
+                    "tool_calls": [{
+                        "id": "call_a",
+                        "type": "function",
+                        "function": {"name": "list_dir", "arguments": "{\"path\":\".\"}"},
+                    }],
+                },
+                {"role": "tool", "tool_call_id": "call_a", "content": "a.txt"},
+                {"role": "user", "content": "Only text files."},
+            ])
+        );
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "list_dir");
+    }
+
+    #[test]
+    fn reply_tool_calls_and_cached_usage_are_parsed() {
+        let body = json!({
+            "choices": [{"message": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [
+                    {"type": "function", "function": {"name": "read_file", "arguments": "{\"path\": \"a.txt\"}"}},
+                    {"id": "call_b", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\": "}},
+                ],
+            }}],
+            "usage": {
+                "prompt_tokens": 100,
+                "completion_tokens": 7,
+                "prompt_tokens_details": {"cached_tokens": 40},
+            },
+        });
+
+        let response = parse_response(&body).unwrap();
+        assert_eq!(
+            response.content,
+            vec![
+                ContentBlock::ToolUse {
+                    id: "call_0".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!({"path": "a.txt"}),
+                },
+                ContentBlock::ToolUse {
+                    id: "call_b".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!("{\"path\": "),
+                },
+            ]
+        );
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                input_tokens: 60,
+                output_tokens: 7,
+                cache_read_tokens: 40,
+                cache_write_tokens: 0,
+            })
+        );
+
+        let without_usage = json!({"choices": [{"message": {"content": "Hi"}}]});
+        assert_eq!(parse_response(&without_usage).unwrap().usage, None);
+        assert!(parse_response(&json!({"error": "nope"})).is_err());
+    }
+
+    #[test]
+    fn streamed_deltas_are_folded_into_one_message() {
+        let chunks = [
+            json!({"choices": [{"delta": {"role": "assistant", "content": "Let me "}}]}),
+            json!({"choices": [{"delta": {"content": "look."}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_c", "function": {"name": "read_", "arguments": ""}}]}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"name": "file", "arguments": "{\"path\":"}}]}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": "\"c.txt\"}"}}]}}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 20, "completion_tokens": 9}}),
+        ];
+        let mut body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .collect();
+        body.push_str("data: [DONE]\n\n");
+        let (url, request) = serve(200, vec![(Duration::ZERO, body)]);
+
+        let mut streamed = Vec::new();
+        let response = provider(&url)
+            .complete_streaming("system", &[Message::user("Read c.txt")], &[], &mut |text| {
+                streamed.push(text.to_string())
+            })
+            .unwrap();
+
+        let sent: Value = serde_json::from_str(&request.recv().unwrap()).unwrap();
+        assert_eq!(sent["stream"], true);
+        assert_eq!(sent["stream_options"]["include_usage"], true);
+        assert_eq!(streamed, ["Let me ", "look."]);
+        assert_eq!(
+            response.content,
+            vec![
+                ContentBlock::Text {
+                    text: "Let me look.".to_string()
+                },
+                ContentBlock::ToolUse {
+                    id: "call_c".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!({"path": "c.txt"}),
+                },
+            ]
+        );
+        assert_eq!(response.usage.unwrap().output_tokens, 9);
+    }
+}
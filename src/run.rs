@@ -0,0 +1,156 @@
+//! Setting up and executing an agent run, shared by the `run` command and
+//! the agent service.
+
+use crate::agent::AgentLoop;
+use crate::approval::{ApprovalGate, Prompter};
+use crate::cancel::CancelToken;
+use crate::config::{Config, Settings};
+use crate::cost::{Price, PriceTable};
+use crate::events::{Events, Sink};
+use crate::output::CliError;
+use crate::policy::Policy;
+use crate::provider::{self, Provider};
+use crate::session::{Session, SessionStore};
+use crate::tools::{ServiceTool, Spill, ToolRegistry};
+use std::time::{Duration, Instant};
+
+/// What to run.
+#[derive(Debug, Clone, Default)]
+pub struct RunRequest {
+    /// Task of a new session, or the follow-up message when resuming.
+    pub task: Option<String>,
+    /// Session to continue.
+    pub resume: Option<String>,
+    /// Settings overriding the config files, e.g. `("max_iterations", "5")`;
+    /// callers check the values first to report them in their own terms.
+    pub settings: Vec<(String, String)>,
+    /// Approve calls the policy would ask about.
+    pub auto_approve: bool,
+}
+
+/// A run ready to execute; its session is already saved.
+pub struct Run {
+    pub session: Session,
+    settings: Settings,
+    price: Option<Price>,
+    provider: Box<dyn Provider>,
+    tools: ToolRegistry,
+    store: SessionStore,
+    cancel: CancelToken,
+    events: Events,
+}
+
+/// Resolve the config of `request` and open or create its session.
+///
+/// The run gets the `services` tools on top of the configured ones. Its
+/// events go to `sink`; `cancel` stops it.
+pub fn prepare(
+    request: &RunRequest,
+    services: Vec<ServiceTool>,
+    prompter: Box<dyn Prompter>,
+    sink: Option<Box<dyn Sink>>,
+    cancel: CancelToken,
+) -> Result<Run, CliError> {
+    let task = request.task.as_deref();
+    let resume = request.resume.as_deref();
+    if task.is_none() && resume.is_none() {
+        return Err(CliError::invalid_argument(
+            "A run needs a task or a session to resume",
+        ));
+    }
+
+    let flags: Vec<(&str, &str)> = request
+        .settings
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let config = Config::resolve(&flags).map_err(CliError::invalid_config)?;
+    let settings = config.settings.clone();
+
+    let policy =
+        Policy::new(&config.policy, settings.approval_policy).map_err(CliError::invalid_config)?;
+    let price = PriceTable::new(&config.prices)
+        .map_err(CliError::invalid_config)?
+        .lookup(&settings.model);
+    if settings.max_cost.is_some() && price.is_none() {
+        return Err(CliError::invalid_config(format!(
+            "No price for model '{}'; add a [[prices]] entry to use --max-cost",
+            settings.model
+        )));
+    }
+
+    let provider = provider::from_settings(&settings, &cancel)?;
+    let store = SessionStore::open()?;
+    let session = match (resume, task) {
+        (Some(id), follow_up) => {
+            let mut session = store.load(id).map_err(CliError::not_found)?;
+            session.resume(follow_up)?;
+            session.provider = settings.provider.to_string();
+            session.model = settings.model.clone();
+            session
+        }
+        (None, Some(task)) => Session::new(task, &settings),
+        (None, None) => unreachable!("checked above"),
+    };
+
+    let events = Events::new(sink, &session.id);
+    let gate = ApprovalGate::new(policy, prompter, cancel.clone())
+        .with_auto_approve(request.auto_approve)
+        .with_events(events.clone());
+    let spill = Spill::new(
+        store.session_dir(&session.id),
+        settings.tool_output_limit as usize,
+    );
+    // `read_output` goes first so that no service tool can take its name.
+    let tools = ToolRegistry::from_config(&config, &cancel)
+        .map_err(CliError::invalid_config)?
+        .with_spill(spill)
+        .with_services(services)
+        .with_approval(gate);
+    store.save(&session)?;
+
+    Ok(Run {
+        session,
+        settings,
+        price,
+        provider,
+        tools,
+        store,
+        cancel,
+        events,
+    })
+}
+
+impl Run {
+    /// Why service tools were left out of the run.
+    pub fn conflicts(&self) -> &[String] {
+        self.tools.conflicts()
+    }
+
+    /// Run the agent until the session ends and save it.
+    pub fn execute(mut self) -> Result<Session, String> {
+        let settings = &self.settings;
+        let mut agent = AgentLoop::new(
+            self.provider.as_mut(),
+            &mut self.tools,
+            settings.max_iterations,
+        )
+        .with_token_budget(settings.max_tokens)
+        .with_compaction(settings.compact_strategy, settings.compact_threshold)
+        .with_cancel(self.cancel)
+        .with_store(self.store.clone())
+        .with_events(self.events);
+        if let Some(price) = self.price {
+            agent = agent.with_price(price);
+        }
+        if let Some(limit) = settings.max_cost {
+            agent = agent.with_cost_cap(limit, provider::max_output_tokens(settings));
+        }
+        if let Some(ms) = settings.run_timeout_ms {
+            agent = agent.with_deadline(Instant::now() + Duration::from_millis(ms));
+        }
+        agent.run(&mut self.session);
+        self.store.save(&self.session)?;
+        Ok(self.session)
+    }
+}
@@ -0,0 +1,632 @@
+//! Agent service for other plugins.
+//!
+//! `adi.agent-loop.agent` offers the agent without going through CLI
+//! arguments. Every method takes and returns a JSON object; `methods` lists
+//! their schemas. A failed call returns `{"code", "message"}` with the error
+//! codes of `--output json`.
+//!
+//! `start_run` returns once the session is set up and leaves the run going
+//! in the background. Poll `get_run` for its status and events, which are
+//! the events of `run --stream`, and stop it with `cancel_run`. Only the
+//! latest `MAX_EVENTS` events of a run and the latest `MAX_FINISHED_RUNS`
+//! finished runs are kept; the sessions themselves stay saved.
+//!
+//! Nobody watches these runs, so tool calls the policy asks about are
+//! denied unless the run is started with `auto_approve`. Any plugin can
+//! call `start_run`, so it may only set `auto_approve` or override the
+//! user-only settings (`approval_policy`, `service_read_only`, `workspace`)
+//! if the user config sets `service_overrides = "allow"`.
+//!
+//! Runs get the tools of the `adi.agent-loop.tool-provider` service;
+//! `register_tool_provider` adds those of other services with the same
+//! contract, since the host cannot list them. See `tools::service`.
+
+use crate::approval;
+use crate::cancel::CancelToken;
+use crate::config::{self, Config, ServiceOverrides};
+use crate::events::Sink;
+use crate::output::{self, CliError};
+use crate::run::{self, RunRequest};
+use crate::session::SessionStore;
+use crate::tools::{self, ToolRegistry};
+use lib_plugin_abi::PluginContext;
+use serde_json::{json, Value};
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Service ID of the agent service.
+pub const SERVICE_AGENT: &str = "adi.agent-loop.agent";
+
+/// A service method with JSON schemas of its arguments and result.
+pub struct Method {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub input: Value,
+    pub output: Value,
+}
+
+/// Methods of the agent service.
+pub fn methods() -> Vec<Method> {
+    let run_id = json!({"type": "string", "description": "Run ID returned by start_run"});
+    vec![
+        Method {
+            name: "start_run",
+            description: "Start an agent run in the background",
+            input: json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "Task of a new session, or the follow-up message when resuming",
+                    },
+                    "resume": {"type": "string", "description": "Session to continue"},
+                    "settings": {
+                        "type": "object",
+                        "description": "Settings overriding the config files, e.g. {\"max_iterations\": 5}; user-only keys need service_overrides = \"allow\"",
+                        "additionalProperties": {"type": ["string", "number", "boolean"]},
+                    },
+                    "auto_approve": {
+                        "type": "boolean",
+                        "description": "Approve tool calls the policy would ask about; needs service_overrides = \"allow\"",
+                    },
+                },
+            }),
+            output: json!({
+                "type": "object",
+                "properties": {
+                    "run": {"type": "string"},
+                    "session": {"type": "string"},
+                },
+                "required": ["run", "session"],
+            }),
+        },
+        Method {
+            name: "get_run",
+            description: "Get the status, summary and events of a run",
+            input: json!({
+                "type": "object",
+                "properties": {
+                    "run": run_id,
+                    "after": {
+                        "type": "integer",
+                        "minimum": 0,
+                        "description": "Number of events already seen; events dropped for being too old are skipped",
+                    },
+                },
+                "required": ["run"],
+            }),
+            output: json!({
+                "type": "object",
+                "properties": {
+                    "run": {"type": "string"},
+                    "session": {"type": "string"},
+                    "status": {
+                        "type": "string",
+                        "description": "'running', or the outcome of the finished run",
+                    },
+                    "summary": {
+                        "type": ["object", "null"],
+                        "description": "The data of 'run --output json' once the run has finished",
+                    },
+                    "error": {"type": ["string", "null"]},
+                    "events": {"type": "array", "items": {"type": "object"}},
+                    "next": {
+                        "type": "integer",
+                        "description": "Value of 'after' for the next call",
+                    },
+                },
+                "required": ["run", "session", "status", "events", "next"],
+            }),
+        },
+        Method {
+            name: "cancel_run",
+            description: "Stop a run",
+            input: json!({
+                "type": "object",
+                "properties": {"run": run_id},
+                "required": ["run"],
+            }),
+            output: json!({
+                "type": "object",
+                "properties": {
+                    "run": {"type": "string"},
+                    "cancelled": {
+                        "type": "boolean",
+                        "description": "False if the run had already finished",
+                    },
+                },
+                "required": ["run", "cancelled"],
+            }),
+        },
+        Method {
+            name: "list_sessions",
+            description: "List saved sessions",
+            input: json!({"type": "object"}),
+            output: json!({
+                "type": "object",
+                "properties": {
+                    "sessions": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "id": {"type": "string"},
+                                "parent": {"type": ["string", "null"]},
+                                "forked_at": {"type": ["integer", "null"]},
+                                "created_at": {"type": "integer"},
+                                "updated_at": {"type": "integer"},
+                                "provider": {"type": "string"},
+                                "model": {"type": "string"},
+                                "task": {"type": "string"},
+                                "outcome": {"type": "string"},
+                                "iterations": {"type": "integer"},
+                                "cost_usd": {"type": ["number", "null"]},
+                            },
+                        },
+                    },
+                },
+                "required": ["sessions"],
+            }),
+        },
+        Method {
+            name: "list_tools",
+            description: "List the tools available to runs",
+            input: json!({"type": "object"}),
+            output: json!({
+                "type": "object",
+                "properties": {
+                    "tools": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "name": {"type": "string"},
+                                "description": {"type": "string"},
+                                "parameters": {"type": "object"},
+                                "source": {"type": "string"},
+                                "read_only": {"type": "boolean"},
+                            },
+                        },
+                    },
+                },
+                "required": ["tools"],
+            }),
+        },
+        Method {
+            name: "register_tool_provider",
+            description: "Offer the tools of a tool-provider service registered under another ID to every run",
+            input: json!({
+                "type": "object",
+                "properties": {
+                    "service": {
+                        "type": "string",
+                        "description": "ID of a registered service with list_tools and call_tool methods",
+                    },
+                },
+                "required": ["service"],
+            }),
+            output: json!({
+                "type": "object",
+                "properties": {
+                    "service": {"type": "string"},
+                    "registered": {
+                        "type": "boolean",
+                        "description": "False if the service was already registered or is found without registering",
+                    },
+                },
+                "required": ["service", "registered"],
+            }),
+        },
+    ]
+}
+
+/// Call `method` with JSON `args`; `None` if there is no such method.
+pub fn call(ctx: &PluginContext, method: &str, args: &str) -> Option<Result<Value, CliError>> {
+    let handler: fn(&PluginContext, &Value) -> Result<Value, CliError> = match method {
+        "start_run" => start_run,
+        "get_run" => get_run,
+        "cancel_run" => cancel_run,
+        "list_sessions" => list_sessions,
+        "list_tools" => list_tools,
+        "register_tool_provider" => register_tool_provider,
+        _ => return None,
+    };
+    let args = if args.trim().is_empty() {
+        Ok(json!({}))
+    } else {
+        serde_json::from_str(args)
+            .map_err(|e| CliError::invalid_argument(format!("Invalid arguments: {}", e)))
+    };
+    Some(args.and_then(|args| handler(ctx, &args)))
+}
+
+// === Runs ===
+
+/// Events kept per run; older ones are dropped.
+const MAX_EVENTS: usize = 1000;
+/// Finished runs kept for `get_run`; the ones that finished first go first.
+const MAX_FINISHED_RUNS: usize = 64;
+
+/// Runs started by this plugin instance, by run ID.
+static RUNS: Mutex<BTreeMap<String, Arc<Mutex<RunState>>>> = Mutex::new(BTreeMap::new());
+static NEXT_RUN: AtomicU64 = AtomicU64::new(1);
+/// Threads of the runs, joined on plugin cleanup.
+static THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+#[derive(Default)]
+struct RunState {
+    session: String,
+    cancel: CancelToken,
+    events: VecDeque<Value>,
+    /// Events dropped from the front of `events`.
+    dropped: usize,
+    /// The run summary once the run has finished, or why it failed.
+    result: Option<Result<Value, String>>,
+    finished_at: Option<Instant>,
+}
+
+impl RunState {
+    fn record(&mut self, event: Value) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+
+    /// Events from the `after`-th on, skipping dropped ones, and the number
+    /// of events recorded so far.
+    fn events_after(&self, after: usize) -> (Vec<&Value>, usize) {
+        let skip = after.saturating_sub(self.dropped);
+        let events = self.events.iter().skip(skip).collect();
+        (events, self.dropped + self.events.len())
+    }
+}
+
+/// Keeps the events of a run for `get_run`.
+struct Recorder(Arc<Mutex<RunState>>);
+
+impl Sink for Recorder {
+    fn send(&mut self, event: &str) {
+        if let Ok(event) = serde_json::from_str(event) {
+            lock(&self.0).record(event);
+        }
+    }
+}
+
+fn start_run(ctx: &PluginContext, args: &Value) -> Result<Value, CliError> {
+    let request = run_request(args)?;
+    let overrides = Config::resolve(&[])
+        .map_err(CliError::invalid_config)?
+        .settings
+        .service_overrides;
+    check_overrides(&request, overrides)?;
+    let services = tools::provider_tools(ctx);
+    let cancel = CancelToken::new();
+    let state = Arc::new(Mutex::new(RunState {
+        cancel: cancel.clone(),
+        ..RunState::default()
+    }));
+
+    // Providers and tools stay on the run's thread; only the outcome of the
+    // setup and the service tools left out come back.
+    let (ready, setup) = mpsc::channel();
+    let shared = state.clone();
+    let handle = thread::spawn(move || {
+        let body = panic::catch_unwind(AssertUnwindSafe(|| {
+            let recorder = Box::new(Recorder(shared.clone()));
+            let run = match run::prepare(
+                &request,
+                services,
+                approval::unattended(),
+                Some(recorder),
+                cancel,
+            ) {
+                Ok(run) => run,
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                    return None;
+                }
+            };
+            let _ = ready.send(Ok((run.session.id.clone(), run.conflicts().to_vec())));
+            Some(
+                run.execute()
+                    .map(|session| output::run_summary(&session.summary)),
+            )
+        }));
+        let result = match body {
+            Ok(Some(result)) => result,
+            Ok(None) => return,
+            Err(payload) => {
+                let message = format!("The run panicked: {}", panic_message(&*payload));
+                // Only reaches `start_run` if the panic came during setup.
+                let _ = ready.send(Err(message.clone().into()));
+                Err(message)
+            }
+        };
+        let mut state = lock(&shared);
+        state.result = Some(result);
+        state.finished_at = Some(Instant::now());
+        drop(state);
+        evict_finished();
+    });
+    {
+        let mut threads = THREADS.lock().unwrap_or_else(|e| e.into_inner());
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(handle);
+    }
+    let (session, conflicts) = setup
+        .recv()
+        .map_err(|_| "The run stopped during setup".to_string())??;
+    for conflict in &conflicts {
+        ctx.host().error(conflict);
+    }
+
+    lock(&state).session = session.clone();
+    let id = format!("run-{}", NEXT_RUN.fetch_add(1, Ordering::SeqCst));
+    RUNS.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(id.clone(), state);
+    evict_finished();
+    Ok(json!({"run": id, "session": session}))
+}
+
+/// Drop the runs that finished first beyond `MAX_FINISHED_RUNS`.
+fn evict_finished() {
+    let mut runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut finished: Vec<(Instant, String)> = runs
+        .iter()
+        .filter_map(|(id, state)| lock(state).finished_at.map(|at| (at, id.clone())))
+        .collect();
+    if finished.len() <= MAX_FINISHED_RUNS {
+        return;
+    }
+    finished.sort();
+    for (_, id) in &finished[..finished.len() - MAX_FINISHED_RUNS] {
+        runs.remove(id);
+    }
+}
+
+/// Text of a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+/// Wait up to `timeout` for the run threads to finish, e.g. after their
+/// runs were cancelled. Threads still going after that are left detached.
+pub fn join_runs(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let threads = std::mem::take(&mut *THREADS.lock().unwrap_or_else(|e| e.into_inner()));
+    for thread in threads {
+        while !thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if thread.is_finished() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn get_run(_ctx: &PluginContext, args: &Value) -> Result<Value, CliError> {
+    let (id, state) = find_run(args)?;
+    let after = args["after"].as_u64().unwrap_or(0) as usize;
+    let state = lock(&state);
+    let (events, next) = state.events_after(after);
+    let (status, summary, error) = match &state.result {
+        None => ("running", Value::Null, None),
+        Some(Ok(summary)) => (
+            summary["outcome"].as_str().unwrap_or("failed"),
+            summary.clone(),
+            None,
+        ),
+        Some(Err(e)) => ("failed", Value::Null, Some(e.as_str())),
+    };
+    Ok(json!({
+        "run": id,
+        "session": state.session,
+        "status": status,
+        "summary": summary,
+        "error": error,
+        "events": events,
+        "next": next,
+    }))
+}
+
+fn cancel_run(_ctx: &PluginContext, args: &Value) -> Result<Value, CliError> {
+    let (id, state) = find_run(args)?;
+    let state = lock(&state);
+    let running = state.result.is_none();
+    if running {
+        state.cancel.cancel();
+    }
+    Ok(json!({"run": id, "cancelled": running}))
+}
+
+fn list_sessions(_ctx: &PluginContext, _args: &Value) -> Result<Value, CliError> {
+    let sessions = SessionStore::open()?.list()?;
+    Ok(json!({
+        "sessions": sessions.iter().map(output::session_entry).collect::<Vec<_>>(),
+    }))
+}
+
+fn list_tools(ctx: &PluginContext, _args: &Value) -> Result<Value, CliError> {
+    let config = Config::load().map_err(CliError::invalid_config)?;
+    let registry = ToolRegistry::from_config(&config, &CancelToken::new())
+        .map_err(CliError::invalid_config)?
+        .with_services(tools::provider_tools(ctx));
+    for conflict in registry.conflicts() {
+        ctx.host().error(conflict);
+    }
+    Ok(json!({ "tools": output::tool_list(&registry) }))
+}
+
+fn register_tool_provider(ctx: &PluginContext, args: &Value) -> Result<Value, CliError> {
+    let service = args["service"]
+        .as_str()
+        .ok_or_else(|| CliError::invalid_argument("'service' must be a service ID"))?;
+    if ctx.host().lookup_svc(service).is_none() {
+        return Err(CliError::not_found(format!(
+            "No service {} is registered with the host",
+            service
+        )));
+    }
+    let registered = tools::register_provider(service);
+    Ok(json!({"service": service, "registered": registered}))
+}
+
+/// The `start_run` arguments as a run request.
+fn run_request(args: &Value) -> Result<RunRequest, CliError> {
+    let text = |key: &str| -> Result<Option<String>, CliError> {
+        match &args[key] {
+            Value::Null => Ok(None),
+            Value::String(value) => Ok(Some(value.clone())),
+            _ => Err(CliError::invalid_argument(format!(
+                "'{}' must be a string",
+                key
+            ))),
+        }
+    };
+    Ok(RunRequest {
+        task: text("task")?,
+        resume: text("resume")?,
+        settings: settings(&args["settings"])?,
+        auto_approve: args["auto_approve"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Reject a run that loosens approval unless `overrides` allows it.
+///
+/// `service_overrides` itself only comes from the user config.
+fn check_overrides(request: &RunRequest, overrides: ServiceOverrides) -> Result<(), CliError> {
+    let needs_allow = |what: String| {
+        CliError::invalid_argument(format!(
+            "{} needs service_overrides = \"allow\" in the user config",
+            what
+        ))
+    };
+    for (key, _) in &request.settings {
+        if key == "service_overrides" {
+            return Err(CliError::invalid_argument(
+                "settings.service_overrides can only be set in the user config",
+            ));
+        }
+        if config::schema::USER_ONLY.contains(&key.as_str()) && overrides == ServiceOverrides::Deny
+        {
+            return Err(needs_allow(format!("settings.{}", key)));
+        }
+    }
+    if request.auto_approve && overrides == ServiceOverrides::Deny {
+        return Err(needs_allow("auto_approve".to_string()));
+    }
+    Ok(())
+}
+
+/// Setting overrides as checked `(key, value)` pairs.
+fn settings(settings: &Value) -> Result<Vec<(String, String)>, CliError> {
+    let Some(settings) = settings.as_object() else {
+        return match settings {
+            Value::Null => Ok(Vec::new()),
+            _ => Err(CliError::invalid_argument("'settings' must be an object")),
+        };
+    };
+    settings
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Number(_) | Value::Bool(_) => value.to_string(),
+                _ => {
+                    return Err(CliError::invalid_argument(format!(
+                        "settings.{}: expected a string, number or boolean",
+                        key
+                    )))
+                }
+            };
+            config::schema::lookup(key)
+                .and_then(|spec| spec.check(&value))
+                .map_err(|e| CliError::invalid_argument(format!("settings.{}: {}", key, e)))?;
+            Ok((key.clone(), value))
+        })
+        .collect()
+}
+
+fn find_run(args: &Value) -> Result<(String, Arc<Mutex<RunState>>), CliError> {
+    let id = args["run"]
+        .as_str()
+        .ok_or_else(|| CliError::invalid_argument("'run' must be a run ID"))?;
+    let runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    let state = runs
+        .get(id)
+        .ok_or_else(|| CliError::not_found(format!("No run with ID {}", id)))?;
+    Ok((id.to_string(), state.clone()))
+}
+
+/// A run's state, even if its thread panicked while holding the lock.
+fn lock(state: &Mutex<RunState>) -> MutexGuard<'_, RunState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_events_are_dropped_but_counted() {
+        let mut state = RunState::default();
+        for index in 0..MAX_EVENTS + 5 {
+            state.record(json!({ "index": index }));
+        }
+
+        let (events, next) = state.events_after(0);
+        assert_eq!(next, MAX_EVENTS + 5);
+        assert_eq!(events.len(), MAX_EVENTS);
+        assert_eq!(events[0]["index"], 5);
+
+        let (events, next) = state.events_after(MAX_EVENTS + 3);
+        assert_eq!(next, MAX_EVENTS + 5);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["index"], MAX_EVENTS + 3);
+        assert!(state.events_after(next).0.is_empty());
+    }
+
+    #[test]
+    fn panic_messages() {
+        let payload = panic::catch_unwind(|| panic!("plain")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "plain");
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "formatted 1");
+    }
+
+    #[test]
+    fn start_run_cannot_loosen_approval_unless_the_user_allows_it() {
+        let loosening = [
+            json!({"task": "t", "auto_approve": true}),
+            json!({"task": "t", "settings": {"approval_policy": "auto"}}),
+            json!({"task": "t", "settings": {"service_read_only": "trust"}}),
+            json!({"task": "t", "settings": {"workspace": "/"}}),
+        ];
+        for args in &loosening {
+            let request = run_request(args).unwrap();
+            let err = check_overrides(&request, ServiceOverrides::Deny).unwrap_err();
+            assert!(err.message.contains("service_overrides"), "{}", err.message);
+            assert!(check_overrides(&request, ServiceOverrides::Allow).is_ok());
+        }
+
+        let request =
+            run_request(&json!({"task": "t", "settings": {"max_iterations": 3}})).unwrap();
+        assert!(check_overrides(&request, ServiceOverrides::Deny).is_ok());
+        let request =
+            run_request(&json!({"task": "t", "settings": {"service_overrides": "allow"}})).unwrap();
+        assert!(check_overrides(&request, ServiceOverrides::Allow).is_err());
+    }
+}
@@ -0,0 +1,622 @@
+//! Saved transcripts of agent runs.
+//!
+//! Each session is a JSON file under `$XDG_DATA_HOME/adi/agent/sessions`
+//! (or `~/.local/share/adi/agent/sessions`). It is rewritten after every
+//! iteration so an interrupted run can be resumed. Files a run produces,
+//! such as oversized tool output, go in a directory named after the session.
+
+use crate::agent::{RunOutcome, RunSummary, ToolCallRecord};
+use crate::config::{self, Settings};
+use crate::context::Compaction;
+use crate::message::{ContentBlock, Message, Role, Usage};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Token usage, cost and time of one model reply.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplyStats {
+    pub usage: Usage,
+    /// Model call plus the tool calls it requested, in milliseconds.
+    pub duration_ms: u64,
+    /// Model that replied; empty in sessions saved before it was recorded.
+    #[serde(default)]
+    pub model: String,
+    /// Unix timestamp of the reply; 0 if not recorded.
+    #[serde(default)]
+    pub at: u64,
+    /// Cost in USD at the price of the time; `None` if the model had no price.
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
+}
+
+/// A run's transcript together with its summary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub provider: String,
+    pub model: String,
+    pub messages: Vec<Message>,
+    /// Stats of each assistant message, in transcript order.
+    #[serde(default)]
+    pub replies: Vec<ReplyStats>,
+    pub summary: RunSummary,
+    /// Session this one was forked from.
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Turn of the parent the fork starts from.
+    #[serde(default)]
+    pub forked_at: Option<usize>,
+    /// Context compactions, oldest first; the latest applies.
+    #[serde(default)]
+    pub compactions: Vec<Compaction>,
+}
+
+impl Session {
+    pub fn new(task: &str, settings: &Settings) -> Self {
+        let now = now();
+        Self {
+            id: new_id(now),
+            created_at: now,
+            updated_at: now,
+            provider: settings.provider.to_string(),
+            model: settings.model.clone(),
+            messages: vec![Message::user(task)],
+            replies: Vec::new(),
+            summary: RunSummary {
+                task: task.to_string(),
+                outcome: RunOutcome::Pending,
+                iterations: 0,
+                final_answer: None,
+                tool_calls: Vec::new(),
+                usage: Default::default(),
+                duration_ms: 0,
+                budget: None,
+                cost_usd: None,
+                cost_cap: None,
+            },
+            parent: None,
+            forked_at: None,
+            compactions: Vec::new(),
+        }
+    }
+
+    /// Number of turns; a turn is a user message and the model's reply to it.
+    pub fn turns(&self) -> usize {
+        self.messages
+            .iter()
+            .filter(|message| message.role == Role::User)
+            .count()
+    }
+
+    /// New session with the transcript up to and including the user message
+    /// of `turn`, whose text is replaced by `message` if given.
+    ///
+    /// The model's reply to that turn is dropped, so resuming the fork asks
+    /// again from there. Usage and duration cover the replies that are kept.
+    pub fn fork(&self, turn: usize, message: Option<&str>) -> Result<Session, String> {
+        let turns = self.turns();
+        if turn == 0 || turn > turns {
+            return Err(format!(
+                "Invalid turn {}: session {} has turns 1 to {}",
+                turn, self.id, turns
+            ));
+        }
+
+        let mut seen = 0;
+        let end = self
+            .messages
+            .iter()
+            .position(|m| {
+                seen += usize::from(m.role == Role::User);
+                seen == turn
+            })
+            .unwrap_or(0);
+        let mut messages = self.messages[..=end].to_vec();
+        if let Some(text) = message {
+            let last = &mut messages[end];
+            last.content
+                .retain(|block| !matches!(block, ContentBlock::Text { .. }));
+            last.content.push(ContentBlock::Text {
+                text: text.to_string(),
+            });
+        }
+
+        let task = match message {
+            Some(text) if turn == 1 => text.to_string(),
+            _ => self.summary.task.clone(),
+        };
+        let replies = messages
+            .iter()
+            .filter(|m| m.role == Role::Assistant)
+            .count();
+        let kept: Vec<ReplyStats> = self.replies.iter().take(replies).cloned().collect();
+        let mut usage = Usage::default();
+        let mut cost_usd = None;
+        for reply in &kept {
+            usage.add(reply.usage);
+            if let Some(cost) = reply.cost_usd {
+                *cost_usd.get_or_insert(0.0) += cost;
+            }
+        }
+        let now = now();
+        Ok(Session {
+            id: new_id(now),
+            created_at: now,
+            updated_at: now,
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            summary: RunSummary {
+                task,
+                outcome: RunOutcome::Pending,
+                iterations: replies as u64,
+                final_answer: None,
+                tool_calls: tool_call_records(&messages),
+                usage,
+                duration_ms: kept.iter().map(|reply| reply.duration_ms).sum(),
+                budget: None,
+                cost_usd,
+                cost_cap: None,
+            },
+            replies: kept,
+            messages,
+            parent: Some(self.id.clone()),
+            forked_at: Some(turn),
+            compactions: self
+                .compactions
+                .iter()
+                .filter(|compaction| compaction.end < end)
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Prepare a saved session for another run, optionally with a follow-up message.
+    pub fn resume(&mut self, follow_up: Option<&str>) -> Result<(), String> {
+        match follow_up {
+            Some(text) => match self.messages.last_mut() {
+                // Tool results and the follow-up share one user turn.
+                Some(last) if last.role == Role::User => last.content.push(ContentBlock::Text {
+                    text: text.to_string(),
+                }),
+                _ => self.messages.push(Message::user(text)),
+            },
+            None if self.summary.outcome == RunOutcome::Completed => {
+                return Err(format!(
+                    "Session {} already completed; pass a follow-up task to continue it",
+                    self.id
+                ))
+            }
+            None => {}
+        }
+        self.summary.final_answer = None;
+        Ok(())
+    }
+
+    /// Human-readable transcript with turn numbers.
+    pub fn render_transcript(&self) -> String {
+        let mut output = String::new();
+        let mut turn = 0;
+        for (index, message) in self.messages.iter().enumerate() {
+            for compaction in self.compactions.iter().filter(|c| c.end == index) {
+                output.push_str(&format!(
+                    "[context compacted ({}): {} earlier messages replaced, ~{} -> ~{} tokens]\n\n",
+                    compaction.strategy.as_str(),
+                    compaction.end - 1,
+                    compaction.tokens_before,
+                    compaction.tokens_after
+                ));
+            }
+            let role = match message.role {
+                Role::User => {
+                    turn += 1;
+                    output.push_str(&format!("--- turn {} ---\n\n", turn));
+                    "user"
+                }
+                Role::Assistant => "assistant",
+            };
+            for block in &message.content {
+                match block {
+                    ContentBlock::Text { text } => {
+                        output.push_str(&format!("[{}]\n{}\n\n", role, text.trim_end()));
+                    }
+                    ContentBlock::ToolUse { name, input, .. } => {
+                        output.push_str(&format!("[tool call] {} {}\n\n", name, input));
+                    }
+                    ContentBlock::ToolResult {
+                        content, is_error, ..
+                    } => {
+                        let label = if *is_error {
+                            "tool error"
+                        } else {
+                            "tool result"
+                        };
+                        output.push_str(&format!("[{}]\n{}\n\n", label, content.trim_end()));
+                    }
+                }
+            }
+        }
+        output.trim_end().to_string()
+    }
+}
+
+/// Tool call records rebuilt from a transcript; iteration `n` is the `n`th reply.
+fn tool_call_records(messages: &[Message]) -> Vec<ToolCallRecord> {
+    let failed: Vec<&str> = messages
+        .iter()
+        .flat_map(|m| &m.content)
+        .filter_map(|block| match block {
+            ContentBlock::ToolResult {
+                tool_use_id,
+                is_error: true,
+                ..
+            } => Some(tool_use_id.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    messages
+        .iter()
+        .filter(|m| m.role == Role::Assistant)
+        .enumerate()
+        .flat_map(|(index, message)| {
+            message
+                .tool_calls()
+                .into_iter()
+                .map(move |call| (index as u64 + 1, call))
+        })
+        .map(|(iteration, call)| ToolCallRecord {
+            iteration,
+            is_error: failed.contains(&call.id.as_str()),
+            name: call.name,
+            input: call.input,
+        })
+        .collect()
+}
+
+/// Directory of session files.
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn open() -> Result<Self, String> {
+        Ok(Self {
+            dir: sessions_dir()?,
+        })
+    }
+
+    pub fn save(&self, session: &Session) -> Result<(), String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        let content = serde_json::to_vec_pretty(session)
+            .map_err(|e| format!("Failed to serialize session: {}", e))?;
+        config::write_atomic(&self.dir.join(format!("{}.json", session.id)), &content)
+    }
+
+    /// Directory for files belonging to session `id`, such as saved tool output.
+    pub fn session_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Load the session whose id is or starts with `id`.
+    pub fn load(&self, id: &str) -> Result<Session, String> {
+        let mut matches: Vec<String> = self
+            .ids()?
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(id))
+            .collect();
+        if let Some(exact) = matches.iter().position(|candidate| candidate == id) {
+            matches = vec![matches.swap_remove(exact)];
+        }
+        match matches.as_slice() {
+            [] => Err(format!("No session matches '{}'", id)),
+            [found] => self.read(found),
+            _ => Err(format!(
+                "'{}' matches {} sessions; use more characters of the id",
+                id,
+                matches.len()
+            )),
+        }
+    }
+
+    /// All readable sessions, most recently updated first.
+    pub fn list(&self) -> Result<Vec<Session>, String> {
+        let mut sessions: Vec<Session> = self
+            .ids()?
+            .iter()
+            .filter_map(|id| self.read(id).ok())
+            .collect();
+        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then(b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    fn read(&self, id: &str) -> Result<Session, String> {
+        let path = self.dir.join(format!("{}.json", id));
+        let content =
+            fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&content)
+            .map_err(|e| format!("Invalid session file {}: {}", path.display(), e))
+    }
+
+    fn ids(&self) -> Result<Vec<String>, String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.dir.display(), e)),
+        };
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()?
+                    .strip_suffix(".json")
+                    .map(String::from)
+            })
+            .collect())
+    }
+}
+
+/// `$XDG_DATA_HOME/adi/agent/sessions`, falling back to `~/.local/share`.
+fn sessions_dir() -> Result<PathBuf, String> {
+    let base = match std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("HOME")
+            .filter(|v| !v.is_empty())
+            .map(|home| PathBuf::from(home).join(".local").join("share"))
+            .ok_or("Cannot locate data directory: neither XDG_DATA_HOME nor HOME is set")?,
+    };
+    Ok(base.join("adi").join("agent").join("sessions"))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Sortable id such as `20240131-235959-1a2b`.
+fn new_id(now: u64) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let suffix = (nanos ^ std::process::id().rotate_left(16)) & 0xffff;
+    let (date, time) = civil(now);
+    format!(
+        "{}-{}-{:04x}",
+        date.replace('-', ""),
+        time.replace(':', ""),
+        suffix
+    )
+}
+
+/// UTC date and time of a unix timestamp, e.g. `2024-01-31 23:59:59 UTC`.
+pub fn format_time(timestamp: u64) -> String {
+    let (date, time) = civil(timestamp);
+    format!("{} {} UTC", date, time)
+}
+
+/// UTC date of a unix timestamp, e.g. `2024-01-31`.
+pub fn format_date(timestamp: u64) -> String {
+    civil(timestamp).0
+}
+
+/// `(YYYY-MM-DD, HH:MM:SS)` in UTC.
+fn civil(timestamp: u64) -> (String, String) {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+
+    // Days since 1970-01-01 to a proleptic Gregorian date.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3_600,
+            seconds % 3_600 / 60,
+            seconds % 60
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> SessionStore {
+        let dir = std::env::temp_dir().join(format!(
+            "adi-agent-sessions-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        SessionStore { dir }
+    }
+
+    fn session(id: &str, task: &str) -> Session {
+        let mut session = Session::new(task, &Settings::default());
+        session.id = id.to_string();
+        session
+    }
+
+    #[test]
+    fn saved_sessions_load_by_id_or_unique_prefix() {
+        let store = store("load");
+        assert!(store.list().unwrap().is_empty());
+        let mut first = session("20240131-235959-1a2b", "first");
+        first.updated_at = 1;
+        store.save(&first).unwrap();
+        store
+            .save(&session("20240131-235959-1a2", "second"))
+            .unwrap();
+        store
+            .save(&session("20240201-000000-ffff", "third"))
+            .unwrap();
+
+        assert_eq!(store.load("20240201").unwrap().summary.task, "third");
+        // An exact id wins over the longer ids it is a prefix of.
+        assert_eq!(
+            store.load("20240131-235959-1a2").unwrap().summary.task,
+            "second"
+        );
+        let err = store.load("20240131").unwrap_err();
+        assert!(err.contains("matches 2 sessions"), "{}", err);
+        assert_eq!(store.load("2023").unwrap_err(), "No session matches '2023'");
+        assert_eq!(store.list().unwrap().last().unwrap().summary.task, "first");
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn resume_adds_the_follow_up_to_the_last_user_turn() {
+        let mut session = session("s", "task");
+        session
+            .messages
+            .push(Message::assistant(vec![ContentBlock::ToolUse {
+                id: "call_1".to_string(),
+                name: "list_dir".to_string(),
+                input: serde_json::json!({}),
+            }]));
+        session.messages.push(Message {
+            role: Role::User,
+            content: vec![ContentBlock::ToolResult {
+                tool_use_id: "call_1".to_string(),
+                content: "a.txt".to_string(),
+                is_error: false,
+            }],
+        });
+        session.summary.outcome = RunOutcome::Cancelled;
+
+        session.resume(None).unwrap();
+        assert_eq!(session.messages.len(), 3);
+        session.resume(Some("Only a.txt")).unwrap();
+        assert_eq!(session.messages.len(), 3);
+        assert_eq!(session.messages[2].text(), "Only a.txt");
+
+        session
+            .messages
+            .push(Message::assistant(vec![ContentBlock::Text {
+                text: "Done.".to_string(),
+            }]));
+        session.summary.outcome = RunOutcome::Completed;
+        session.summary.final_answer = Some("Done.".to_string());
+        assert!(session
+            .resume(None)
+            .unwrap_err()
+            .contains("already completed"));
+        session.resume(Some("Now b.txt")).unwrap();
+        assert_eq!(session.messages.len(), 5);
+        assert_eq!(session.turns(), 3);
+        assert_eq!(session.summary.final_answer, None);
+    }
+
+    #[test]
+    fn fork_keeps_the_transcript_up_to_a_turn() {
+        let mut parent = session("parent", "List the files");
+        let reply = |content: ContentBlock, input_tokens: u64| {
+            (
+                Message::assistant(vec![content]),
+                ReplyStats {
+                    usage: Usage {
+                        input_tokens,
+                        ..Default::default()
+                    },
+                    duration_ms: 10,
+                    cost_usd: Some(0.5),
+                    ..Default::default()
+                },
+            )
+        };
+        let turns = [
+            reply(
+                ContentBlock::ToolUse {
+                    id: "call_1".to_string(),
+                    name: "list_dir".to_string(),
+                    input: serde_json::json!({"path": "."}),
+                },
+                100,
+            ),
+            reply(
+                ContentBlock::Text {
+                    text: "a.txt".to_string(),
+                },
+                200,
+            ),
+            reply(
+                ContentBlock::Text {
+                    text: "b.txt".to_string(),
+                },
+                300,
+            ),
+        ];
+        let follow_ups = [
+            Message {
+                role: Role::User,
+                content: vec![ContentBlock::ToolResult {
+                    tool_use_id: "call_1".to_string(),
+                    content: "denied".to_string(),
+                    is_error: true,
+                }],
+            },
+            Message::user("And the hidden ones?"),
+        ];
+        for (index, (message, stats)) in turns.into_iter().enumerate() {
+            parent.messages.push(message);
+            parent.replies.push(stats);
+            if let Some(follow_up) = follow_ups.get(index) {
+                parent.messages.push(follow_up.clone());
+            }
+        }
+        parent.summary.outcome = RunOutcome::Completed;
+        assert_eq!(parent.turns(), 3);
+
+        let fork = parent.fork(3, Some("Only the hidden ones")).unwrap();
+        assert_eq!(fork.parent.as_deref(), Some("parent"));
+        assert_eq!(fork.forked_at, Some(3));
+        assert_ne!(fork.id, parent.id);
+        assert_eq!(fork.messages.len(), 5);
+        assert_eq!(fork.messages[4].text(), "Only the hidden ones");
+        assert_eq!(fork.summary.task, "List the files");
+        assert_eq!(fork.summary.outcome, RunOutcome::Pending);
+        assert_eq!(fork.summary.iterations, 2);
+        assert_eq!(fork.summary.usage.input_tokens, 300);
+        assert_eq!(fork.summary.cost_usd, Some(1.0));
+        assert_eq!(fork.summary.duration_ms, 20);
+        assert_eq!(fork.summary.tool_calls.len(), 1);
+        assert!(fork.summary.tool_calls[0].is_error);
+
+        let fork = parent.fork(1, Some("List the directories")).unwrap();
+        assert_eq!(fork.messages.len(), 1);
+        assert_eq!(fork.summary.task, "List the directories");
+        assert!(fork.replies.is_empty());
+
+        for turn in [0, 4] {
+            let err = parent.fork(turn, None).unwrap_err();
+            assert!(err.contains("has turns 1 to 3"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951_868_799), "2000-02-29 23:59:59 UTC");
+        assert_eq!(format_date(1_706_745_599), "2024-01-31");
+        assert_eq!(new_id(1_706_745_599).get(..15), Some("20240131-235959"));
+    }
+}
@@ -0,0 +1,44 @@
+//! "Did you mean" suggestions for mistyped names.
+
+/// Closest candidate to `input`, if it is a plausible typo.
+pub fn closest<'a>(input: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let threshold = (input.len() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(input, candidate), candidate))
+        .filter(|(d, _)| *d <= threshold)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, candidate)| candidate)
+}
+
+/// `message` followed by a suggestion when one is close enough.
+pub fn with_suggestion<'a>(
+    message: String,
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> String {
+    match closest(input, candidates) {
+        Some(candidate) => format!("{} (did you mean '{}'?)", message, candidate),
+        None => message,
+    }
+}
+
+/// Levenshtein edit distance.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(current)
+            };
+            prev = current;
+        }
+    }
+    row[b.len()]
+}
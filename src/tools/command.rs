@@ -0,0 +1,137 @@
+//! Tools backed by a subprocess, declared as `[[tools]]` in agent.toml.
+
+use super::process::{self, RunOptions};
+use super::Tool;
+use crate::cancel::CancelToken;
+use crate::config::{ToolConfig, ToolInput};
+use crate::message::{ToolDefinition, ToolOutput};
+use serde_json::{json, Value};
+use std::process::Command;
+use std::time::Duration;
+
+pub struct CommandTool {
+    config: ToolConfig,
+    timeout: Duration,
+    cancel: CancelToken,
+}
+
+impl CommandTool {
+    /// `default_timeout_ms` applies when the entry sets no `timeout_ms`.
+    pub fn new(config: ToolConfig, default_timeout_ms: u64, cancel: CancelToken) -> Self {
+        let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(default_timeout_ms));
+        Self {
+            config,
+            timeout,
+            cancel,
+        }
+    }
+}
+
+impl Tool for CommandTool {
+    fn definition(&self) -> ToolDefinition {
+        let parameters = self
+            .config
+            .parameters
+            .as_ref()
+            .and_then(|table| serde_json::to_value(table).ok())
+            .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+        ToolDefinition {
+            name: self.config.name.clone(),
+            description: self
+                .config
+                .description
+                .clone()
+                .unwrap_or_else(|| format!("Runs `{}`", self.config.command)),
+            parameters,
+        }
+    }
+
+    fn source(&self) -> String {
+        format!("command: {}", self.config.command)
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        let args = match self
+            .config
+            .args
+            .iter()
+            .map(|arg| template(arg, input))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(args) => args,
+            Err(e) => return ToolOutput::error(e),
+        };
+
+        let mut command = Command::new(&self.config.command);
+        command.args(args).envs(&self.config.env);
+        if let Some(cwd) = &self.config.cwd {
+            command.current_dir(cwd);
+        }
+        let stdin = match self.config.input {
+            ToolInput::Stdin => Some(input.to_string().into_bytes()),
+            ToolInput::Argv => None,
+        };
+
+        let options = RunOptions {
+            stdin,
+            timeout: self.timeout,
+            output_limit: process::DEFAULT_OUTPUT_LIMIT,
+            cancel: self.cancel.clone(),
+        };
+        let output = match process::run(command, options) {
+            Ok(output) => output,
+            Err(e) => return ToolOutput::error(e),
+        };
+
+        let mut content = output.stdout.text;
+        if !output.stderr.text.is_empty() {
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str("[stderr]\n");
+            content.push_str(&output.stderr.text);
+        }
+
+        let failure = if output.cancelled {
+            "Command cancelled".to_string()
+        } else if output.timed_out {
+            format!("Command timed out after {}ms", self.timeout.as_millis())
+        } else {
+            match output.exit_code {
+                Some(0) => return ToolOutput::ok(content),
+                Some(code) => format!("Command failed with exit code {}", code),
+                None => "Command was killed by a signal".to_string(),
+            }
+        };
+        ToolOutput::error(format!("{}\n{}", failure, content))
+    }
+}
+
+/// Replace `{param}` placeholders in `arg` with values from `input`.
+///
+/// Strings are inserted verbatim, other values as JSON.
+fn template(arg: &str, input: &Value) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start + 1..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + 1 + len];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            result.push_str(&rest[..=start]);
+            rest = &rest[start + 1..];
+            continue;
+        }
+
+        result.push_str(&rest[..start]);
+        match input.get(name) {
+            Some(Value::String(s)) => result.push_str(s),
+            Some(Value::Null) | None => return Err(format!("Missing argument: {}", name)),
+            Some(value) => result.push_str(&value.to_string()),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
@@ -0,0 +1,555 @@
+//! Built-in filesystem tools confined to a workspace root.
+
+use super::Tool;
+use crate::message::{ToolDefinition, ToolOutput};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Lines returned by `read_file` when no `limit` is given.
+const DEFAULT_READ_LIMIT: usize = 2000;
+
+/// Maximum number of paths returned by `glob`.
+const MAX_GLOB_RESULTS: usize = 1000;
+
+/// Changed lines shown in an approval preview.
+const MAX_PREVIEW_LINES: usize = 200;
+
+/// Directory the file tools may access.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(root: &Path) -> Result<Self, String> {
+        let root = root
+            .canonicalize()
+            .map_err(|e| format!("Invalid workspace {}: {}", root.display(), e))?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve `path` against the root, rejecting anything that escapes it.
+    ///
+    /// `..` components are resolved lexically. Each existing component is
+    /// then checked with `symlink_metadata`: symlinks are followed to their
+    /// target, and a dangling symlink is rejected, since writing through it
+    /// would create its target wherever it points.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let outside = || format!("Path is outside the workspace: {}", path);
+        let joined = self.root.join(path);
+        let mut normalized = PathBuf::new();
+        for component in joined.components() {
+            match component {
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                Component::CurDir => {}
+                other => normalized.push(other),
+            }
+        }
+        let relative = normalized.strip_prefix(&self.root).map_err(|_| outside())?;
+
+        let mut resolved = self.root.clone();
+        let mut components = relative.components();
+        while let Some(component) = components.next() {
+            let candidate = resolved.join(component);
+            match fs::symlink_metadata(&candidate) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    resolved = candidate
+                        .canonicalize()
+                        .map_err(|_| format!("Path goes through a dangling symlink: {}", path))?;
+                }
+                Ok(_) => resolved = candidate,
+                // Nothing below a missing component exists, so no links either.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    resolved = candidate;
+                    resolved.extend(components);
+                    break;
+                }
+                Err(e) => return Err(format!("Cannot resolve {}: {}", path, e)),
+            }
+        }
+
+        if resolved.starts_with(&self.root) {
+            Ok(resolved)
+        } else {
+            Err(outside())
+        }
+    }
+
+    /// `path` relative to the root, for display.
+    pub fn relative(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if relative.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            relative.display().to_string()
+        }
+    }
+}
+
+/// All built-in filesystem tools for `workspace`.
+pub fn tools(workspace: &Workspace) -> Vec<Box<dyn Tool>> {
+    vec![
+        Box::new(ReadFile(workspace.clone())),
+        Box::new(WriteFile(workspace.clone())),
+        Box::new(EditFile(workspace.clone())),
+        Box::new(ListDir(workspace.clone())),
+        Box::new(Glob(workspace.clone())),
+    ]
+}
+
+fn str_arg<'a>(input: &'a Value, name: &str) -> Result<&'a str, String> {
+    input[name]
+        .as_str()
+        .ok_or_else(|| format!("Argument '{}' must be a string", name))
+}
+
+fn run(result: Result<String, String>) -> ToolOutput {
+    match result {
+        Ok(content) => ToolOutput::ok(content),
+        Err(e) => ToolOutput::error(e),
+    }
+}
+
+/// Line diff of `old` and `new` with the unchanged head and tail left out.
+fn diff(display: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let head = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let tail = old[head..]
+        .iter()
+        .rev()
+        .zip(new[head..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let removed = old[head..old.len() - tail].iter().map(|line| ("-", line));
+    let added = new[head..new.len() - tail].iter().map(|line| ("+", line));
+    let changes: Vec<_> = removed.chain(added).collect();
+
+    let mut output = format!(
+        "--- {}\n+++ {}\n@@ line {} @@\n",
+        display,
+        display,
+        head + 1
+    );
+    for (sign, line) in changes.iter().take(MAX_PREVIEW_LINES) {
+        output.push_str(&format!("{}{}\n", sign, line));
+    }
+    if changes.len() > MAX_PREVIEW_LINES {
+        output.push_str(&format!(
+            "... ({} more changed lines)\n",
+            changes.len() - MAX_PREVIEW_LINES
+        ));
+    }
+    output
+}
+
+// === read_file ===
+
+pub struct ReadFile(Workspace);
+
+impl ReadFile {
+    fn read(&self, input: &Value) -> Result<String, String> {
+        let path = self.0.resolve(str_arg(input, "path")?)?;
+        let content = fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", self.0.relative(&path), e))?;
+        Ok(numbered_lines(&String::from_utf8_lossy(&content), input))
+    }
+}
+
+/// Lines `offset..offset + limit` of `content`, as given in `input`, with line numbers.
+pub(super) fn numbered_lines(content: &str, input: &Value) -> String {
+    let offset = input["offset"].as_u64().unwrap_or(1).max(1) as usize;
+    let limit = input["limit"]
+        .as_u64()
+        .map(|n| n as usize)
+        .unwrap_or(DEFAULT_READ_LIMIT);
+
+    let total = content.lines().count();
+    let mut output = String::new();
+    for (index, line) in content.lines().enumerate().skip(offset - 1).take(limit) {
+        output.push_str(&format!("{:>6}\t{}\n", index + 1, line));
+    }
+    let last = (offset - 1 + limit).min(total);
+    if last < total {
+        output.push_str(&format!(
+            "... ({} more lines; continue with offset {})\n",
+            total - last,
+            last + 1
+        ));
+    }
+    if output.is_empty() {
+        output = format!("(no lines in range; file has {} lines)", total);
+    }
+    output
+}
+
+impl Tool for ReadFile {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "read_file".to_string(),
+            description: "Read a text file with line numbers. Use offset and limit to read \
+                          a range of lines."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File path relative to the workspace"},
+                    "offset": {"type": "integer", "description": "First line to read (1-based)"},
+                    "limit": {"type": "integer", "description": "Maximum number of lines"}
+                },
+                "required": ["path"]
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        run(self.read(input))
+    }
+}
+
+// === write_file ===
+
+pub struct WriteFile(Workspace);
+
+impl WriteFile {
+    fn write(&self, input: &Value) -> Result<String, String> {
+        let path = self.0.resolve(str_arg(input, "path")?)?;
+        let content = str_arg(input, "content")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", self.0.relative(parent), e))?;
+        }
+        fs::write(&path, content)
+            .map_err(|e| format!("Failed to write {}: {}", self.0.relative(&path), e))?;
+        Ok(format!(
+            "Wrote {} bytes to {}",
+            content.len(),
+            self.0.relative(&path)
+        ))
+    }
+}
+
+impl Tool for WriteFile {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "write_file".to_string(),
+            description: "Create or overwrite a file with the given content.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File path relative to the workspace"},
+                    "content": {"type": "string", "description": "Full file content"}
+                },
+                "required": ["path", "content"]
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn preview(&self, input: &Value) -> Option<String> {
+        let path = self.0.resolve(str_arg(input, "path").ok()?).ok()?;
+        let content = str_arg(input, "content").ok()?;
+        let display = self.0.relative(&path);
+        match fs::read_to_string(&path) {
+            Ok(old) => Some(diff(&display, &old, content)),
+            Err(_) => Some(format!("(new file)\n{}", diff(&display, "", content))),
+        }
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        run(self.write(input))
+    }
+}
+
+// === edit_file ===
+
+pub struct EditFile(Workspace);
+
+impl EditFile {
+    /// Path, current content and content after the edit.
+    fn apply(&self, input: &Value) -> Result<(PathBuf, String, String, usize), String> {
+        let path = self.0.resolve(str_arg(input, "path")?)?;
+        let old = str_arg(input, "old_string")?;
+        let new = str_arg(input, "new_string")?;
+        let replace_all = input["replace_all"].as_bool().unwrap_or(false);
+        if old.is_empty() {
+            return Err("old_string must not be empty".to_string());
+        }
+
+        let display = self.0.relative(&path);
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", display, e))?;
+        let count = content.matches(old).count();
+        let updated = match count {
+            0 => return Err(format!("old_string not found in {}", display)),
+            1 => content.replacen(old, new, 1),
+            _ if replace_all => content.replace(old, new),
+            _ => {
+                return Err(format!(
+                    "old_string occurs {} times in {}; add context to make it unique \
+                     or set replace_all",
+                    count, display
+                ))
+            }
+        };
+        Ok((path, content, updated, count))
+    }
+
+    fn edit(&self, input: &Value) -> Result<String, String> {
+        let (path, _, updated, count) = self.apply(input)?;
+        let display = self.0.relative(&path);
+        fs::write(&path, updated).map_err(|e| format!("Failed to write {}: {}", display, e))?;
+        Ok(format!("Replaced {} occurrence(s) in {}", count, display))
+    }
+}
+
+impl Tool for EditFile {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "edit_file".to_string(),
+            description: "Replace an exact string in a file. old_string must match exactly \
+                          once unless replace_all is set."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "File path relative to the workspace"},
+                    "old_string": {"type": "string", "description": "Exact text to replace"},
+                    "new_string": {"type": "string", "description": "Replacement text"},
+                    "replace_all": {"type": "boolean", "description": "Replace every occurrence"}
+                },
+                "required": ["path", "old_string", "new_string"]
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn preview(&self, input: &Value) -> Option<String> {
+        Some(match self.apply(input) {
+            Ok((path, content, updated, _)) => diff(&self.0.relative(&path), &content, &updated),
+            Err(e) => format!("(the edit will fail: {})", e),
+        })
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        run(self.edit(input))
+    }
+}
+
+// === list_dir ===
+
+pub struct ListDir(Workspace);
+
+impl ListDir {
+    fn list(&self, input: &Value) -> Result<String, String> {
+        let path = self.0.resolve(input["path"].as_str().unwrap_or("."))?;
+        let display = self.0.relative(&path);
+        let entries =
+            fs::read_dir(&path).map_err(|e| format!("Failed to list {}: {}", display, e))?;
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let mut name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    name.push('/');
+                }
+                name
+            })
+            .collect();
+        names.sort();
+
+        if names.is_empty() {
+            Ok(format!("{} is empty", display))
+        } else {
+            Ok(names.join("\n"))
+        }
+    }
+}
+
+impl Tool for ListDir {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "list_dir".to_string(),
+            description: "List the entries of a directory. Directories end with '/'.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "Directory relative to the workspace (default: .)"}
+                }
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        run(self.list(input))
+    }
+}
+
+// === glob ===
+
+pub struct Glob(Workspace);
+
+impl Glob {
+    fn search(&self, input: &Value) -> Result<String, String> {
+        let pattern = str_arg(input, "pattern")?;
+        if Path::new(pattern).is_absolute()
+            || Path::new(pattern)
+                .components()
+                .any(|c| c == Component::ParentDir)
+        {
+            return Err("pattern must be relative to the workspace".to_string());
+        }
+
+        let root = glob::Pattern::escape(&self.0.root().to_string_lossy());
+        let paths = glob::glob(&format!("{}/{}", root, pattern))
+            .map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?;
+
+        let mut matches: Vec<String> = paths
+            .filter_map(|entry| entry.ok())
+            .filter(|path| self.0.resolve(&path.to_string_lossy()).is_ok())
+            .map(|path| self.0.relative(&path))
+            .take(MAX_GLOB_RESULTS + 1)
+            .collect();
+        matches.sort();
+
+        if matches.is_empty() {
+            return Ok(format!("No files match {}", pattern));
+        }
+        let truncated = matches.len() > MAX_GLOB_RESULTS;
+        matches.truncate(MAX_GLOB_RESULTS);
+        let mut output = matches.join("\n");
+        if truncated {
+            output.push_str(&format!(
+                "\n... (showing the first {} matches)",
+                MAX_GLOB_RESULTS
+            ));
+        }
+        Ok(output)
+    }
+}
+
+impl Tool for Glob {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "glob".to_string(),
+            description: "Find files matching a glob pattern such as 'src/**/*.rs'.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {"type": "string", "description": "Glob pattern relative to the workspace"}
+                },
+                "required": ["pattern"]
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        run(self.search(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A workspace in a fresh temporary directory, next to an `outside` directory.
+    fn workspace(name: &str) -> (PathBuf, Workspace) {
+        let base =
+            std::env::temp_dir().join(format!("adi-agent-fs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("ws/src")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        let workspace = Workspace::new(&base.join("ws")).unwrap();
+        (base, workspace)
+    }
+
+    #[test]
+    fn paths_inside_the_root_resolve() {
+        let (base, ws) = workspace("inside");
+        let root = ws.root().to_path_buf();
+        assert_eq!(ws.resolve("src").unwrap(), root.join("src"));
+        assert_eq!(
+            ws.resolve("src/../new/file.txt").unwrap(),
+            root.join("new/file.txt")
+        );
+        assert_eq!(ws.resolve(".").unwrap(), root);
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn parent_components_cannot_escape() {
+        let (base, ws) = workspace("parent");
+        assert!(ws.resolve("../outside/x").is_err());
+        assert!(ws.resolve("src/../../outside").is_err());
+        assert!(ws.resolve("/etc/passwd").is_err());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn existing_symlink_cannot_escape() {
+        let (base, ws) = workspace("link");
+        symlink(base.join("outside"), base.join("ws/out")).unwrap();
+        symlink(base.join("ws/src"), base.join("ws/alias")).unwrap();
+        assert!(ws.resolve("out").is_err());
+        assert!(ws.resolve("out/new.txt").is_err());
+        assert_eq!(
+            ws.resolve("alias/a.rs").unwrap(),
+            ws.root().join("src/a.rs")
+        );
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn dangling_symlink_cannot_escape() {
+        let (base, ws) = workspace("dangling");
+        symlink(base.join("outside/x"), base.join("ws/out")).unwrap();
+        symlink(base.join("outside/dir"), base.join("ws/outdir")).unwrap();
+        assert!(ws.resolve("out").is_err());
+        assert!(ws.resolve("outdir/file.txt").is_err());
+
+        let write = WriteFile(ws.clone()).call(&json!({"path": "out", "content": "x"}));
+        assert!(write.is_error);
+        assert!(!base.join("outside/x").exists());
+        fs::remove_dir_all(base).unwrap();
+    }
+}
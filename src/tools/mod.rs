@@ -0,0 +1,209 @@
+//! Tools offered to the model and the registry that dispatches them.
+
+mod command;
+mod fs;
+mod process;
+mod service;
+mod shell;
+mod spill;
+
+use crate::agent::ToolDispatcher;
+use crate::approval::ApprovalGate;
+use crate::cancel::CancelToken;
+use crate::config::{Config, ServiceReadOnly};
+use crate::message::{ToolCall, ToolDefinition, ToolOutput};
+use serde_json::Value;
+use std::path::PathBuf;
+
+pub use command::CommandTool;
+pub use fs::Workspace;
+pub use service::{provider_tools, register_provider, ServiceTool};
+pub use shell::ShellTool;
+pub use spill::Spill;
+
+/// A tool the agent can call.
+pub trait Tool {
+    fn definition(&self) -> ToolDefinition;
+
+    /// Where the tool comes from, e.g. `command: my-command`.
+    fn source(&self) -> String;
+
+    /// Whether the tool only reads; read-only calls skip approval.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Preview of what a call would change, shown when asking for approval.
+    fn preview(&self, _input: &Value) -> Option<String> {
+        None
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput;
+}
+
+/// Named set of tools available to a run.
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+    /// Checked before every call.
+    gate: Option<ApprovalGate>,
+    /// Applied to every result.
+    spill: Option<Spill>,
+    service_read_only: ServiceReadOnly,
+    /// Why service tools were left out.
+    conflicts: Vec<String>,
+}
+
+impl ToolRegistry {
+    /// Registry with the built-in tools and the `[[tools]]` entries of `config`.
+    ///
+    /// Subprocesses started by the tools are killed once `cancel` fires.
+    pub fn from_config(config: &Config, cancel: &CancelToken) -> Result<Self, String> {
+        let root = match &config.settings.workspace {
+            Some(path) => PathBuf::from(path),
+            None => std::env::current_dir()
+                .map_err(|e| format!("Cannot determine current directory: {}", e))?,
+        };
+        let workspace = Workspace::new(&root)?;
+
+        let mut registry = Self {
+            service_read_only: config.settings.service_read_only,
+            ..Self::default()
+        };
+        for tool in fs::tools(&workspace) {
+            registry.register(tool);
+        }
+        registry.register(Box::new(ShellTool::new(
+            workspace,
+            config.settings.timeout_ms,
+            cancel.clone(),
+        )));
+        for tool in &config.tools {
+            registry.register(Box::new(CommandTool::new(
+                tool.clone(),
+                config.settings.timeout_ms,
+                cancel.clone(),
+            )));
+        }
+        Ok(registry)
+    }
+
+    /// Add the tools of tool-provider services.
+    ///
+    /// A service tool never replaces a tool of the same name; it is left out
+    /// and listed in `conflicts`. Its read-only flag only counts when
+    /// `service_read_only` is `trust`.
+    pub fn with_services(mut self, tools: Vec<ServiceTool>) -> Self {
+        for mut tool in tools {
+            let name = tool.definition().name;
+            if let Some(taken) = self.find(&name) {
+                self.conflicts.push(format!(
+                    "Tool {} ({}) is left out: the name is already taken ({})",
+                    name,
+                    tool.source(),
+                    taken.source()
+                ));
+                continue;
+            }
+            if self.service_read_only == ServiceReadOnly::Ignore {
+                tool.read_only = false;
+            }
+            self.register(Box::new(tool));
+        }
+        self
+    }
+
+    /// Why service tools were left out, for the host log.
+    pub fn conflicts(&self) -> &[String] {
+        &self.conflicts
+    }
+
+    /// Check every call with `gate` before running it.
+    pub fn with_approval(mut self, gate: ApprovalGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Cut oversized results with `spill` and offer `read_output` for the saved files.
+    pub fn with_spill(mut self, spill: Spill) -> Self {
+        self.register(Box::new(spill.reader()));
+        self.spill = Some(spill);
+        self
+    }
+
+    /// Add a tool, replacing any tool with the same name.
+    pub fn register(&mut self, tool: Box<dyn Tool>) {
+        let name = tool.definition().name;
+        self.tools.retain(|t| t.definition().name != name);
+        self.tools.push(tool);
+    }
+
+    pub fn find(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools
+            .iter()
+            .find(|tool| tool.definition().name == name)
+            .map(|tool| tool.as_ref())
+    }
+
+    /// Definitions and sources of all tools, in registration order.
+    pub fn list(&self) -> Vec<(ToolDefinition, String)> {
+        self.tools
+            .iter()
+            .map(|tool| (tool.definition(), tool.source()))
+            .collect()
+    }
+}
+
+impl ToolDispatcher for ToolRegistry {
+    fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|tool| tool.definition()).collect()
+    }
+
+    fn dispatch(&mut self, call: &ToolCall) -> ToolOutput {
+        let Some(tool) = self
+            .tools
+            .iter()
+            .find(|tool| tool.definition().name == call.name)
+        else {
+            return ToolOutput::error(format!("Unknown tool: {}", call.name));
+        };
+        if let Err(e) = check_required(&tool.definition().parameters, &call.input) {
+            return ToolOutput::error(e);
+        }
+        if let Some(gate) = &mut self.gate {
+            if let Err(e) = gate.check(tool.as_ref(), call) {
+                return ToolOutput::error(e);
+            }
+        }
+        let output = tool.call(&call.input);
+        match &self.spill {
+            Some(spill) => spill.apply(call, output),
+            None => output,
+        }
+    }
+}
+
+/// Check that `input` is an object carrying every `required` property of `schema`.
+fn check_required(schema: &Value, input: &Value) -> Result<(), String> {
+    let Some(object) = input.as_object() else {
+        return Err(format!(
+            "Invalid arguments: expected a JSON object, got {}",
+            input
+        ));
+    };
+    let missing: Vec<&str> = schema["required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.as_str())
+        .filter(|name| !object.contains_key(*name))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Missing required arguments: {}",
+            missing.join(", ")
+        ))
+    }
+}
@@ -0,0 +1,296 @@
+//! Subprocess execution with a timeout, output caps and cancellation.
+//!
+//! Each process runs in its own process group so that a timeout or a
+//! cancelled run also kills anything it spawned. Output of a process that
+//! escaped the group and keeps the pipes open is read for at most
+//! `READ_GRACE` after the child is gone.
+
+use crate::cancel::CancelToken;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the pipes are read after the child has exited or been killed.
+const READ_GRACE: Duration = Duration::from_millis(500);
+
+/// Bytes kept per output stream; the middle of longer output is dropped.
+pub const DEFAULT_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+/// How to run a subprocess.
+pub struct RunOptions {
+    pub stdin: Option<Vec<u8>>,
+    pub timeout: Duration,
+    pub output_limit: usize,
+    pub cancel: CancelToken,
+}
+
+/// Captured output stream.
+#[derive(Debug, Clone, Default)]
+pub struct Captured {
+    pub text: String,
+    /// Bytes dropped from the middle of the stream.
+    pub dropped: u64,
+}
+
+/// Result of a finished subprocess.
+#[derive(Debug, Clone)]
+pub struct ProcessOutput {
+    /// Exit code; `None` if the process was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: Captured,
+    pub stderr: Captured,
+    pub timed_out: bool,
+    pub cancelled: bool,
+}
+
+/// Run `command` to completion.
+pub fn run(mut command: Command, options: RunOptions) -> Result<ProcessOutput, String> {
+    command
+        .stdin(if options.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+
+    // Pipes are drained on their own threads so a chatty process cannot block.
+    if let (Some(input), Some(mut pipe)) = (options.stdin, child.stdin.take()) {
+        thread::spawn(move || {
+            let _ = pipe.write_all(&input);
+        });
+    }
+    let stdout = drain(child.stdout.take(), options.output_limit);
+    let stderr = drain(child.stderr.take(), options.output_limit);
+
+    let pid = child.id();
+    let deadline = Instant::now() + options.timeout;
+    let mut timed_out = false;
+    let mut cancelled = false;
+    let status = loop {
+        match exited(&child) {
+            Ok(true) => {
+                // Background jobs left behind would keep the pipes open. The
+                // child is not reaped yet, so its group id cannot be reused.
+                kill_group(pid);
+                break child.wait().ok();
+            }
+            Ok(false) => {
+                timed_out = Instant::now() >= deadline;
+                cancelled = options.cancel.is_cancelled();
+                if timed_out || cancelled {
+                    break kill(&mut child);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => {
+                kill(&mut child);
+                return Err(format!("Failed to wait for {}: {}", program, e));
+            }
+        }
+    };
+
+    let read_deadline = Instant::now() + READ_GRACE;
+    Ok(ProcessOutput {
+        exit_code: status.and_then(|s| s.code()),
+        stdout: stdout.finish(read_deadline),
+        stderr: stderr.finish(read_deadline),
+        timed_out,
+        cancelled,
+    })
+}
+
+/// Whether the child has exited, without reaping it.
+#[cfg(unix)]
+fn exited(child: &Child) -> io::Result<bool> {
+    let pid = child.id() as libc::id_t;
+    // Zeroed so that "no state change" leaves `si_signo` at 0.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    match unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) } {
+        0 => Ok(info.si_signo != 0),
+        _ => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn exited(child: &Child) -> io::Result<bool> {
+    // Without process groups there is nothing to kill after the exit, so
+    // reaping here is fine.
+    child.try_wait().map(|status| status.is_some())
+}
+
+/// Kill the child's whole process group, then reap it.
+fn kill(child: &mut Child) -> Option<ExitStatus> {
+    kill_group(child.id());
+    let _ = child.kill();
+    child.wait().ok()
+}
+
+/// Kill the process group led by `pid`.
+fn kill_group(pid: u32) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(pid) {
+        // The child leads its own group, so the group id is its pid.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+}
+
+/// Output read so far from a pipe.
+#[derive(Default)]
+struct Buffer {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    dropped: u64,
+}
+
+impl Buffer {
+    /// Append `chunk`, keeping the first and last `limit / 2` bytes.
+    fn push(&mut self, mut chunk: &[u8], limit: usize) {
+        let head_limit = limit / 2;
+        let tail_limit = limit - head_limit;
+        if self.head.len() < head_limit {
+            let take = chunk.len().min(head_limit - self.head.len());
+            self.head.extend_from_slice(&chunk[..take]);
+            chunk = &chunk[take..];
+        }
+        self.tail.extend(chunk);
+        if self.tail.len() > tail_limit {
+            let excess = self.tail.len() - tail_limit;
+            self.tail.drain(..excess);
+            self.dropped += excess as u64;
+        }
+    }
+}
+
+/// A pipe being read on its own thread.
+struct Drain {
+    buffer: Arc<Mutex<Buffer>>,
+    reader: thread::JoinHandle<()>,
+}
+
+impl Drain {
+    /// What was read once the pipe closes or `deadline` passes; a reader
+    /// still blocked then is left behind.
+    fn finish(self, deadline: Instant) -> Captured {
+        while !self.reader.is_finished() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        let open = !self.reader.is_finished();
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut text = String::from_utf8_lossy(&buffer.head).into_owned();
+        if buffer.dropped > 0 {
+            text.push_str(&format!("\n[... {} bytes truncated ...]\n", buffer.dropped));
+        }
+        text.push_str(&String::from_utf8_lossy(buffer.tail.make_contiguous()));
+        if open {
+            text.push_str("\n[output cut off: a background process kept the stream open]\n");
+        }
+        Captured {
+            text,
+            dropped: buffer.dropped,
+        }
+    }
+}
+
+/// Read `pipe` to the end on a new thread.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>, limit: usize) -> Drain {
+    let buffer = Arc::new(Mutex::new(Buffer::default()));
+    let shared = buffer.clone();
+    let reader = thread::spawn(move || {
+        let Some(mut pipe) = pipe else {
+            return;
+        };
+        let mut chunk = [0u8; 8192];
+        loop {
+            match pipe.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => shared
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(&chunk[..n], limit),
+            }
+        }
+    });
+    Drain { buffer, reader }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str, timeout: Duration) -> ProcessOutput {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        run(
+            command,
+            RunOptions {
+                stdin: None,
+                timeout,
+                output_limit: 16,
+                cancel: CancelToken::new(),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn captures_exit_code_and_both_streams() {
+        let output = sh("echo out; echo err >&2; exit 3", Duration::from_secs(5));
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stdout.text, "out\n");
+        assert_eq!(output.stderr.text, "err\n");
+        assert!(!output.timed_out);
+    }
+
+    #[test]
+    fn long_output_keeps_head_and_tail() {
+        let output = sh("printf '0123456789abcdefghij'", Duration::from_secs(5));
+        assert_eq!(output.stdout.dropped, 4);
+        assert_eq!(
+            output.stdout.text,
+            "01234567\n[... 4 bytes truncated ...]\ncdefghij"
+        );
+    }
+
+    #[test]
+    fn timeout_kills_the_process() {
+        let started = Instant::now();
+        let output = sh("sleep 30", Duration::from_millis(100));
+        assert!(output.timed_out);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn escaped_background_process_does_not_hold_the_call() {
+        // `setsid` moves the sleeper out of the group, so only the read
+        // deadline ends the call.
+        let started = Instant::now();
+        let output = sh("echo started; setsid sleep 30 &", Duration::from_secs(30));
+        assert_eq!(output.exit_code, Some(0));
+        assert!(output.stdout.text.starts_with("started\n"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
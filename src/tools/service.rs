@@ -0,0 +1,163 @@
+//! Tools of other plugins, offered through tool-provider services.
+//!
+//! A plugin offers tools by registering a service with the host that
+//! follows the `adi.agent-loop.tool-provider` contract under that same ID.
+//! The host only looks services up by ID and cannot list them, so just one
+//! plugin is found that way; any other provider registers under its own ID
+//! and passes it to `register_tool_provider` of `adi.agent-loop.agent`.
+//!
+//! The contract:
+//!
+//! - `list_tools` `{}` returns `{"tools": [{"name", "description",
+//!   "parameters", "read_only"}]}`, where `parameters` is a JSON schema and
+//!   `read_only` (default `false`) lets calls skip approval if
+//!   `service_read_only` is `trust`. Like the other user-only keys, that
+//!   comes from the user config or `--service-read-only`, never a project
+//!   file or the environment; `start_run` may only set it if the user config
+//!   sets `service_overrides = "allow"`.
+//! - `call_tool` `{"name", "arguments"}` returns `{"output", "is_error"}`.
+//!
+//! Providers are asked for their tools at the start of every run and by
+//! `tools list`; a provider that is gone or fails to answer is skipped, and
+//! so is a tool whose name a built-in, `[[tools]]` entry or earlier
+//! provider already has.
+//! Calls may come from any thread and are not bound by `timeout_ms`.
+
+use super::Tool;
+use crate::message::{ToolDefinition, ToolOutput};
+use lib_plugin_abi::{PluginContext, ServiceHandle};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+/// Contract of tool-provider services, and the ID looked up at run start.
+pub const SERVICE_TOOL_PROVIDER: &str = "adi.agent-loop.tool-provider";
+
+/// Service IDs passed to `register_tool_provider`, in registration order.
+static PROVIDERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Remember `service` as a tool provider; `false` if it already was one.
+pub fn register_provider(service: &str) -> bool {
+    let mut providers = PROVIDERS.lock().unwrap_or_else(|e| e.into_inner());
+    if service == SERVICE_TOOL_PROVIDER || providers.iter().any(|id| id == service) {
+        return false;
+    }
+    providers.push(service.to_string());
+    true
+}
+
+/// Tools of the provider registered under `SERVICE_TOOL_PROVIDER`, then of
+/// every explicitly registered provider the host still has.
+///
+/// Providers that fail to list their tools are reported to the host log.
+pub fn provider_tools(ctx: &PluginContext) -> Vec<ServiceTool> {
+    let mut providers = vec![SERVICE_TOOL_PROVIDER.to_string()];
+    for service in PROVIDERS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        if !providers.contains(service) {
+            providers.push(service.clone());
+        }
+    }
+    let mut tools = Vec::new();
+    for service in providers {
+        let Some(handle) = ctx.host().lookup_svc(&service) else {
+            continue;
+        };
+        let provider = Arc::new(Provider { service, handle });
+        match provider.list_tools() {
+            Ok(found) => tools.extend(found),
+            Err(e) => ctx.host().error(&e),
+        }
+    }
+    tools
+}
+
+/// A registered tool-provider service.
+struct Provider {
+    service: String,
+    handle: ServiceHandle,
+}
+
+// The handle stays valid while the providing plugin is loaded, and the
+// contract lets calls come from any thread, so runs on the agent service's
+// threads may hold it.
+unsafe impl Send for Provider {}
+unsafe impl Sync for Provider {}
+
+impl Provider {
+    fn invoke(&self, method: &str, args: Value) -> Result<Value, String> {
+        let response = unsafe { self.handle.invoke(method, &args.to_string()) }
+            .map_err(|e| format!("{} {} failed: {}", self.service, method, e.message))?;
+        serde_json::from_str(&response)
+            .map_err(|e| format!("Invalid {} {} response: {}", self.service, method, e))
+    }
+
+    fn list_tools(self: &Arc<Self>) -> Result<Vec<ServiceTool>, String> {
+        let response = self.invoke("list_tools", json!({}))?;
+        let tools = response["tools"].as_array().ok_or_else(|| {
+            format!(
+                "Invalid {} list_tools response: missing tools",
+                self.service
+            )
+        })?;
+        tools
+            .iter()
+            .map(|tool| {
+                let name = tool["name"].as_str().filter(|name| !name.is_empty());
+                let Some(name) = name else {
+                    return Err(format!(
+                        "Invalid {} list_tools response: tool without a name",
+                        self.service
+                    ));
+                };
+                let parameters = match &tool["parameters"] {
+                    Value::Null => json!({"type": "object", "properties": {}}),
+                    parameters => parameters.clone(),
+                };
+                Ok(ServiceTool {
+                    provider: self.clone(),
+                    definition: ToolDefinition {
+                        name: name.to_string(),
+                        description: tool["description"].as_str().unwrap_or_default().to_string(),
+                        parameters,
+                    },
+                    read_only: tool["read_only"].as_bool().unwrap_or(false),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A tool whose calls are routed to a tool-provider service.
+pub struct ServiceTool {
+    provider: Arc<Provider>,
+    definition: ToolDefinition,
+    pub(super) read_only: bool,
+}
+
+impl Tool for ServiceTool {
+    fn definition(&self) -> ToolDefinition {
+        self.definition.clone()
+    }
+
+    fn source(&self) -> String {
+        format!("service: {}", self.provider.service)
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        let args = json!({"name": self.definition.name, "arguments": input});
+        match self.provider.invoke("call_tool", args) {
+            Ok(result) => ToolOutput {
+                content: match &result["output"] {
+                    Value::String(text) => text.clone(),
+                    Value::Null => String::new(),
+                    output => output.to_string(),
+                },
+                is_error: result["is_error"].as_bool().unwrap_or(false),
+            },
+            Err(e) => ToolOutput::error(e),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Built-in shell tool running commands inside the workspace.
+
+use super::fs::Workspace;
+use super::process::{self, Captured, RunOptions};
+use super::Tool;
+use crate::cancel::CancelToken;
+use crate::message::{ToolDefinition, ToolOutput};
+use serde_json::{json, Value};
+use std::process::Command;
+use std::time::Duration;
+
+pub struct ShellTool {
+    workspace: Workspace,
+    timeout_ms: u64,
+    cancel: CancelToken,
+}
+
+impl ShellTool {
+    /// `timeout_ms` applies when a call sets no `timeout_ms`, and caps the
+    /// ones that do.
+    pub fn new(workspace: Workspace, timeout_ms: u64, cancel: CancelToken) -> Self {
+        Self {
+            workspace,
+            timeout_ms,
+            cancel,
+        }
+    }
+
+    fn execute(&self, input: &Value) -> Result<ToolOutput, String> {
+        let script = input["command"]
+            .as_str()
+            .ok_or("Argument 'command' must be a string")?;
+        let timeout_ms = match &input["timeout_ms"] {
+            Value::Null => self.timeout_ms,
+            value => value
+                .as_u64()
+                .filter(|ms| *ms > 0)
+                .ok_or("Argument 'timeout_ms' must be a positive integer")?
+                .min(self.timeout_ms),
+        };
+        let cwd = match input["cwd"].as_str() {
+            Some(path) => self.workspace.resolve(path)?,
+            None => self.workspace.root().to_path_buf(),
+        };
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script).current_dir(&cwd);
+        let output = process::run(
+            command,
+            RunOptions {
+                stdin: None,
+                timeout: Duration::from_millis(timeout_ms),
+                output_limit: process::DEFAULT_OUTPUT_LIMIT,
+                cancel: self.cancel.clone(),
+            },
+        )?;
+
+        let status = if output.cancelled {
+            "cancelled".to_string()
+        } else if output.timed_out {
+            format!("timed out after {}ms", timeout_ms)
+        } else {
+            match output.exit_code {
+                Some(code) => format!("exit code: {}", code),
+                None => "killed by a signal".to_string(),
+            }
+        };
+        let mut content = status;
+        section(&mut content, "stdout", &output.stdout);
+        section(&mut content, "stderr", &output.stderr);
+
+        if output.exit_code == Some(0) && !output.timed_out && !output.cancelled {
+            Ok(ToolOutput::ok(content))
+        } else {
+            Ok(ToolOutput::error(content))
+        }
+    }
+}
+
+/// Append a labelled output stream to `content`, noting any truncation.
+fn section(content: &mut String, label: &str, captured: &Captured) {
+    if captured.text.is_empty() {
+        return;
+    }
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("[{}]", label));
+    if captured.dropped > 0 {
+        content.push_str(&format!(" ({} bytes truncated)", captured.dropped));
+    }
+    content.push('\n');
+    content.push_str(&captured.text);
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+}
+
+impl Tool for ShellTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "shell".to_string(),
+            description: "Run a shell command with `sh -c` in the workspace. Reports the exit \
+                          code and stdout and stderr separately; very long output is truncated \
+                          in the middle."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "command": {"type": "string", "description": "Shell command to run"},
+                    "timeout_ms": {"type": "integer", "description": "Timeout in milliseconds, at most the timeout_ms setting (default: that setting)"},
+                    "cwd": {"type": "string", "description": "Working directory relative to the workspace (default: .)"}
+                },
+                "required": ["command"]
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        self.execute(input).unwrap_or_else(ToolOutput::error)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn call_timeout_is_capped_by_the_setting() {
+        let root = std::env::temp_dir().join(format!("adi-agent-shell-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let tool = ShellTool::new(Workspace::new(&root).unwrap(), 200, CancelToken::new());
+
+        let started = Instant::now();
+        let output = tool.call(&json!({"command": "sleep 30", "timeout_ms": 3_600_000}));
+        assert!(output.is_error);
+        assert!(
+            output.content.starts_with("timed out after 200ms"),
+            "{}",
+            output.content
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let output = tool.call(&json!({"command": "sleep 30", "timeout_ms": 50}));
+        assert!(
+            output.content.starts_with("timed out after 50ms"),
+            "{}",
+            output.content
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+//! Oversized tool results.
+//!
+//! A result longer than `tool_output_limit` characters reaches the model as
+//! its head and tail only. The full text is saved in the session's directory,
+//! where the `read_output` tool can read it by line range.
+
+use super::{fs::numbered_lines, Tool};
+use crate::message::{ToolCall, ToolDefinition, ToolOutput};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+
+const READ_OUTPUT: &str = "read_output";
+
+/// Cuts oversized results and saves them to files.
+pub struct Spill {
+    dir: PathBuf,
+    limit: usize,
+}
+
+impl Spill {
+    /// Save full results in `dir` when they are longer than `limit` characters.
+    pub fn new(dir: PathBuf, limit: usize) -> Self {
+        Self { dir, limit }
+    }
+
+    /// The `read_output` tool for files saved by this spill.
+    pub fn reader(&self) -> ReadOutput {
+        ReadOutput {
+            dir: self.dir.clone(),
+        }
+    }
+
+    /// `output` as sent to the model.
+    pub fn apply(&self, call: &ToolCall, output: ToolOutput) -> ToolOutput {
+        let total = output.content.chars().count();
+        if total <= self.limit {
+            return output;
+        }
+
+        let (head, tail) = split(&output.content, self.limit / 2);
+        let lines = output.content.lines().count();
+        let head_lines = head.matches('\n').count();
+        let tail_start = lines - tail.lines().count() + 1;
+        let omitted = total - head.chars().count() - tail.chars().count();
+
+        let range = if tail_start > head_lines + 1 {
+            format!(
+                " (lines {} to {} of {})",
+                head_lines + 1,
+                tail_start - 1,
+                lines
+            )
+        } else {
+            String::new()
+        };
+        // Paging through a saved file must not save another one.
+        let note = if call.name == READ_OUTPUT {
+            format!(
+                "[... {} characters omitted; request fewer lines with limit]",
+                omitted
+            )
+        } else {
+            match self.save(&call.id, &output.content) {
+                Ok(file) => format!(
+                    "[... {} characters omitted{}. The full output is saved; \
+                     read it with {} {{\"file\": \"{}\", \"offset\": {}, \"limit\": 200}}]",
+                    omitted,
+                    range,
+                    READ_OUTPUT,
+                    file,
+                    head_lines + 1
+                ),
+                Err(e) => format!(
+                    "[... {} characters omitted; the full output could not be saved: {}]",
+                    omitted, e
+                ),
+            }
+        };
+
+        ToolOutput {
+            content: format!("{}\n{}\n{}", head.trim_end_matches('\n'), note, tail),
+            is_error: output.is_error,
+        }
+    }
+
+    /// Write `content` to a new file named after `call_id`, returning its name.
+    fn save(&self, call_id: &str, content: &str) -> Result<String, String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        let stem: String = call_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        // Ids repeat across resumed runs of scripted providers; keep earlier files.
+        let name = (1..)
+            .map(|n| match n {
+                1 => format!("{}.txt", stem),
+                n => format!("{}-{}.txt", stem, n),
+            })
+            .find(|name| !self.dir.join(name).exists())
+            .unwrap_or_default();
+        let path = self.dir.join(&name);
+        fs::write(&path, content)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(name)
+    }
+}
+
+/// About `keep` characters from each end of `text`, cut at line breaks when
+/// there are any.
+fn split(text: &str, keep: usize) -> (&str, &str) {
+    let head_end = text
+        .char_indices()
+        .nth(keep)
+        .map_or(text.len(), |(index, _)| index);
+    let head = &text[..head_end];
+    let head = match head.rfind('\n') {
+        Some(newline) => &head[..=newline],
+        None => head,
+    };
+
+    let count = text.chars().count();
+    let tail_start = text
+        .char_indices()
+        .nth(count.saturating_sub(keep))
+        .map_or(text.len(), |(index, _)| index)
+        .max(head.len());
+    let tail = &text[tail_start..];
+    let tail = match tail.find('\n') {
+        Some(newline) if newline + 1 < tail.len() => &tail[newline + 1..],
+        _ => tail,
+    };
+    (head, tail)
+}
+
+// === read_output ===
+
+/// Reads tool results saved by [`Spill`].
+pub struct ReadOutput {
+    dir: PathBuf,
+}
+
+impl ReadOutput {
+    fn read(&self, input: &Value) -> Result<String, String> {
+        let file = input["file"]
+            .as_str()
+            .ok_or("Argument 'file' must be a string")?;
+        if file.is_empty() || file.starts_with('.') || file.contains(['/', '\\']) {
+            return Err(format!("Invalid output file name: {}", file));
+        }
+        let content = fs::read(self.dir.join(file))
+            .map_err(|e| format!("Failed to read saved output {}: {}", file, e))?;
+        Ok(numbered_lines(&String::from_utf8_lossy(&content), input))
+    }
+}
+
+impl Tool for ReadOutput {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: READ_OUTPUT.to_string(),
+            description: "Read lines of a tool result that was too long to return in full. \
+                          Use offset and limit to read a range of lines."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "file": {"type": "string", "description": "File name given in the cut result"},
+                    "offset": {"type": "integer", "description": "First line to read (1-based)"},
+                    "limit": {"type": "integer", "description": "Maximum number of lines"}
+                },
+                "required": ["file"]
+            }),
+        }
+    }
+
+    fn source(&self) -> String {
+        "built-in".to_string()
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn call(&self, input: &Value) -> ToolOutput {
+        match self.read(input) {
+            Ok(content) => ToolOutput::ok(content),
+            Err(e) => ToolOutput::error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(count: usize) -> String {
+        (1..=count).map(|n| format!("line {:02}\n", n)).collect()
+    }
+
+    fn call(id: &str, name: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            name: name.to_string(),
+            input: json!({}),
+        }
+    }
+
+    #[test]
+    fn split_keeps_whole_lines_from_each_end() {
+        let text = lines(20);
+        assert_eq!(
+            split(&text, 30),
+            ("line 01\nline 02\nline 03\n", "line 18\nline 19\nline 20\n")
+        );
+        assert_eq!(split("abcdefghij", 3), ("abc", "hij"));
+        assert_eq!(split("ééééé", 2), ("éé", "éé"));
+    }
+
+    #[test]
+    fn oversized_results_are_cut_and_saved_for_read_output() {
+        let dir = std::env::temp_dir().join(format!("adi-agent-spill-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let spill = Spill::new(dir.clone(), 60);
+
+        let short = ToolOutput::ok("short");
+        assert_eq!(spill.apply(&call("call_0", "shell"), short.clone()), short);
+
+        let text = lines(20);
+        let cut = spill.apply(&call("call/1", "shell"), ToolOutput::error(text.clone()));
+        assert!(cut.is_error);
+        assert_eq!(
+            cut.content,
+            "line 01\nline 02\nline 03\n[... 112 characters omitted (lines 4 to 17 of 20). \
+             The full output is saved; read it with read_output \
+             {\"file\": \"call_1.txt\", \"offset\": 4, \"limit\": 200}]\n\
+             line 18\nline 19\nline 20\n"
+        );
+        assert_eq!(fs::read_to_string(dir.join("call_1.txt")).unwrap(), text);
+
+        let reader = spill.reader();
+        let page = reader.call(&json!({"file": "call_1.txt", "offset": 4, "limit": 2}));
+        assert_eq!(
+            page.content,
+            "     4\tline 04\n     5\tline 05\n... (15 more lines; continue with offset 6)\n"
+        );
+        assert!(reader.call(&json!({"file": "../agent.toml"})).is_error);
+
+        // A saved file paged too coarsely is not saved again.
+        let cut = spill.apply(&call("call_2", READ_OUTPUT), ToolOutput::ok(text));
+        assert!(cut.content.contains("request fewer lines with limit"));
+        assert!(!dir.join("call_2.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}